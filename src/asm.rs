@@ -0,0 +1,270 @@
+//! A two-pass assembler for the mnemonics printed by the disassembler.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! start:  SET r0 'a'          ; operands split on spaces or commas
+//!         OUT r0
+//!         ADD r0, r0, 1
+//!         EQ r1 r0 0x7b
+//!         JF r1 start
+//!         CALL done
+//! done:   HALT
+//! table:  .data 1, 2, start   ; raw words, labels allowed
+//! text:   .string "hi\n"      ; one word per character, no terminator
+//! ```
+//!
+//! The first pass assigns an address to every label, the second encodes the
+//! words. Mnemonics and register names are case-insensitive.
+
+use std::collections::HashMap;
+use std::fmt;
+use crate::instruction::Opcode;
+
+/// An assembly error and the 1-based source line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based line of the source.
+    pub line: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into a program image starting at address 0.
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    assemble_at(source, 0)
+}
+
+/// Assembles `source` as if it were loaded at `origin`, so labels resolve to
+/// absolute addresses.
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u16>, AsmError> {
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut items = Vec::new();
+    let mut addr = origin as usize;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let err = |message: String| AsmError { line, message };
+        let (found, item) = parse_line(text).map_err(err)?;
+        for label in found {
+            if labels.insert(label.to_string(), addr as u16).is_some() {
+                return Err(err(format!("label '{}' defined twice", label)));
+            }
+        }
+        if let Some(item) = item {
+            addr += item.len();
+            if addr > 32768 {
+                return Err(err(String::from("program does not fit in memory")));
+            }
+            items.push((line, item));
+        }
+    }
+
+    let mut words = Vec::with_capacity(addr - origin as usize);
+    for (line, item) in items {
+        let resolve = |token: &str, raw: bool| {
+            resolve(token, &labels, raw).map_err(|message| AsmError { line, message })
+        };
+        match item {
+            Item::Instruction(op, operands) => {
+                words.push(op as u16);
+                for operand in operands {
+                    words.push(resolve(operand, false)?);
+                }
+            },
+            Item::Data(values) => {
+                for value in values {
+                    words.push(resolve(value, true)?);
+                }
+            },
+            Item::String(chars) => words.extend(chars),
+        }
+    }
+    Ok(words)
+}
+
+enum Item<'a> {
+    Instruction(Opcode, Vec<&'a str>),
+    Data(Vec<&'a str>),
+    String(Vec<u16>),
+}
+
+impl Item<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Item::Instruction(_, operands) => 1 + operands.len(),
+            Item::Data(values) => values.len(),
+            Item::String(chars) => chars.len(),
+        }
+    }
+}
+
+/// Splits a line into its labels and the instruction or directive after them.
+fn parse_line(text: &str) -> Result<(Vec<&str>, Option<Item<'_>>), String> {
+    let mut rest = strip_comment(text, ';').trim();
+    let mut labels = Vec::new();
+    while let Some((label, after)) = rest.split_once(':') {
+        let label = label.trim();
+        if !is_identifier(label) {
+            break;
+        }
+        if register(label).is_some() {
+            return Err(format!("'{}' is a register and cannot be a label", label));
+        }
+        labels.push(label);
+        rest = after.trim();
+    }
+    if rest.is_empty() {
+        return Ok((labels, None));
+    }
+    let (head, args) = match rest.find(char::is_whitespace) {
+        Some(at) => (&rest[..at], rest[at..].trim()),
+        None => (rest, "")
+    };
+    let item = match head.to_ascii_lowercase().as_str() {
+        ".data" => {
+            let values = split_operands(args)?;
+            if values.is_empty() {
+                return Err(String::from(".data needs at least one value"));
+            }
+            Item::Data(values)
+        },
+        ".string" => Item::String(parse_string(args)?),
+        _ => {
+            let op = Opcode::ALL.iter().copied()
+                .find(|op| op.mnemonic().eq_ignore_ascii_case(head))
+                .ok_or_else(|| format!("unknown instruction '{}'", head))?;
+            let operands = split_operands(args)?;
+            if operands.len() != op.arity() {
+                return Err(format!("{} takes {} operand{}, found {}", op, op.arity(), if op.arity() == 1 { "" } else { "s" }, operands.len()));
+            }
+            Item::Instruction(op, operands)
+        }
+    };
+    Ok((labels, Some(item)))
+}
+
+/// Everything before the first `marker` that is not inside quotes.
+pub(crate) fn strip_comment(text: &str, marker: char) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (at, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == marker => return &text[..at],
+            None => {}
+        }
+    }
+    text
+}
+
+/// Splits operands on whitespace and commas, keeping character literals whole.
+fn split_operands(text: &str) -> Result<Vec<&str>, String> {
+    let mut operands = Vec::new();
+    let mut rest = text.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    while !rest.is_empty() {
+        let end = if rest.starts_with('\'') {
+            let mut escaped = false;
+            rest.char_indices().skip(1)
+                .find(|&(_, c)| {
+                    let close = c == '\'' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    close
+                })
+                .map(|(at, _)| at + 1)
+                .ok_or_else(|| format!("unterminated character literal {}", rest))?
+        } else {
+            rest.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(rest.len())
+        };
+        operands.push(&rest[..end]);
+        rest = rest[end..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    Ok(operands)
+}
+
+fn parse_string(text: &str) -> Result<Vec<u16>, String> {
+    let inner = text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| format!(".string needs a quoted string, found '{}'", text))?;
+    unescape(inner)?.chars().map(char_word).collect()
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '\'' | '"')) => c,
+            Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+            None => return Err(String::from("string ends with a backslash"))
+        });
+    }
+    Ok(out)
+}
+
+fn char_word(c: char) -> Result<u16, String> {
+    match c as u32 {
+        code @ 0..=32767 => Ok(code as u16),
+        _ => Err(format!("character '{}' does not fit in 15 bits", c))
+    }
+}
+
+/// Turns one operand into a word. Instruction operands must be literals up to
+/// 32767 or registers; `raw` data words may use the full 16 bits.
+fn resolve(token: &str, labels: &HashMap<String, u16>, raw: bool) -> Result<u16, String> {
+    if let Some(reg) = register(token) {
+        return Ok(32768 + reg);
+    }
+    if let Some(inner) = token.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        let text = unescape(inner)?;
+        let mut chars = text.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => char_word(c),
+            _ => Err(format!("bad character literal {}", token))
+        };
+    }
+    if token.starts_with(|c: char| c.is_ascii_digit()) {
+        let value = match token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => token.parse::<u16>()
+        }.map_err(|_| format!("bad number '{}'", token))?;
+        if !raw && value > 32767 {
+            return Err(format!("literal {} is larger than 32767", value));
+        }
+        return Ok(value);
+    }
+    if is_identifier(token) {
+        return labels.get(token).copied().ok_or_else(|| format!("undefined label '{}'", token));
+    }
+    Err(format!("bad operand '{}'", token))
+}
+
+fn register(token: &str) -> Option<u16> {
+    let digit = token.strip_prefix('r').or_else(|| token.strip_prefix('R'))?;
+    match digit.parse::<u16>() {
+        Ok(reg) if reg < 8 && digit.len() == 1 => Some(reg),
+        _ => None
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
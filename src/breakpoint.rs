@@ -0,0 +1,264 @@
+//! Debugger breakpoints and the conditions that guard them.
+
+use std::fmt;
+use crate::cpu::CPU;
+
+/// A value a condition can look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    /// A number.
+    Literal(u16),
+    /// `rN`: the value of register N.
+    Register(u8),
+    /// `[addr]` or `[rN]`: the memory word at an address.
+    Memory(Address),
+    /// `depth`: number of values on the stack.
+    StackDepth,
+}
+
+/// The address inside `[...]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    /// A fixed address.
+    Literal(u16),
+    /// The address held in register N.
+    Register(u8),
+}
+
+impl Expr {
+    fn eval(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Expr::Literal(value) => value,
+            Expr::Register(reg) => cpu.registers()[reg as usize],
+            Expr::Memory(addr) => {
+                let addr = match addr {
+                    Address::Literal(addr) => addr,
+                    Address::Register(reg) => cpu.registers()[reg as usize],
+                };
+                cpu.memory().get(addr as usize).copied().unwrap_or(0)
+            },
+            Expr::StackDepth => cpu.stack().len() as u16,
+        }
+    }
+}
+
+/// Comparison operators, written as in Rust.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl CmpOp {
+    fn apply(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparison {
+    lhs: Expr,
+    op: CmpOp,
+    rhs: Expr,
+}
+
+/// A breakpoint condition such as `r7 != 0 && [2732] == 2417 || depth > 20`.
+/// `&&` binds tighter than `||`; a bare value means "is not zero".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    any_of: Vec<Vec<Comparison>>,
+    source: String,
+}
+
+impl Condition {
+    /// Parses a condition, reporting the first term that does not make sense.
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let mut any_of = Vec::new();
+        for group in source.split("||") {
+            let mut all_of = Vec::new();
+            for term in group.split("&&") {
+                all_of.push(parse_comparison(term.trim())?);
+            }
+            any_of.push(all_of);
+        }
+        Ok(Condition { any_of, source: source.trim().to_string() })
+    }
+
+    /// Whether the condition holds for the machine's current state.
+    pub fn eval(&self, cpu: &CPU) -> bool {
+        self.any_of.iter().any(|all_of| {
+            all_of.iter().all(|cmp| cmp.op.apply(cmp.lhs.eval(cpu), cmp.rhs.eval(cpu)))
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn parse_comparison(term: &str) -> Result<Comparison, String> {
+    // Two-character operators first so "<=" is not read as "<".
+    for (token, op) in [("==", CmpOp::Eq), ("!=", CmpOp::Ne), ("<=", CmpOp::Le), (">=", CmpOp::Ge), ("<", CmpOp::Lt), (">", CmpOp::Gt)] {
+        if let Some(at) = term.find(token) {
+            let lhs = parse_expr(term[..at].trim())?;
+            let rhs = parse_expr(term[at + token.len()..].trim())?;
+            return Ok(Comparison { lhs, op, rhs });
+        }
+    }
+    Ok(Comparison { lhs: parse_expr(term)?, op: CmpOp::Ne, rhs: Expr::Literal(0) })
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    if text == "depth" {
+        return Ok(Expr::StackDepth);
+    }
+    if let Some(reg) = parse_register(text) {
+        return Ok(Expr::Register(reg));
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        let inner = inner.trim();
+        if let Some(reg) = parse_register(inner) {
+            return Ok(Expr::Memory(Address::Register(reg)));
+        }
+        return parse_number(inner).map(|addr| Expr::Memory(Address::Literal(addr)));
+    }
+    parse_number(text).map(Expr::Literal)
+}
+
+/// `r0`..`r7`, case-insensitive.
+pub fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('r').or_else(|| text.strip_prefix('R'))?;
+    match digit.parse::<u8>() {
+        Ok(reg) if reg < 8 && digit.len() == 1 => Some(reg),
+        _ => None
+    }
+}
+
+/// Decimal or `0x` hexadecimal.
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>()
+    };
+    parsed.map_err(|_| format!("expected a number, register, [address] or depth, found '{}'", text))
+}
+
+/// A stop at an address, optionally only when a condition holds.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    /// Number used by the console commands.
+    pub id: usize,
+    /// Address of the instruction to stop before.
+    pub addr: u16,
+    /// Only stop when this holds.
+    pub condition: Option<Condition>,
+    /// Disabled breakpoints neither count hits nor stop.
+    pub enabled: bool,
+    /// Times execution reached the address with the condition true.
+    pub hits: u64,
+    /// Further hits to let pass before stopping.
+    pub ignore_count: u64,
+}
+
+/// The debugger's breakpoint table.
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// An empty table.
+    pub fn new() -> Breakpoints {
+        Breakpoints::default()
+    }
+
+    /// Adds an enabled breakpoint and returns its id.
+    pub fn add(&mut self, addr: u16, condition: Option<Condition>) -> usize {
+        self.next_id += 1;
+        self.list.push(Breakpoint { id: self.next_id, addr, condition, enabled: true, hits: 0, ignore_count: 0 });
+        self.next_id
+    }
+
+    /// Deletes breakpoint `id`; false if there is none.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|bp| bp.id != id);
+        self.list.len() != len
+    }
+
+    /// Breakpoint `id`, to change its condition, counts or state.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.list.iter_mut().find(|bp| bp.id == id)
+    }
+
+    /// Every breakpoint, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    /// Whether there are no breakpoints.
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Called before each instruction. Counts hits at the program counter and
+    /// returns the id of the first breakpoint that should stop execution.
+    pub fn check(&mut self, cpu: &CPU) -> Option<usize> {
+        let pc = cpu.pc() as u16;
+        let mut stop = None;
+        for bp in self.list.iter_mut().filter(|bp| bp.enabled && bp.addr == pc) {
+            if !bp.condition.as_ref().is_none_or(|cond| cond.eval(cpu)) {
+                continue;
+            }
+            bp.hits += 1;
+            if bp.ignore_count > 0 {
+                bp.ignore_count -= 1;
+            } else if stop.is_none() {
+                stop = Some(bp.id);
+            }
+        }
+        stop
+    }
+
+    /// Like `check` but without counting hits or consuming ignore counts.
+    pub fn matches(&self, cpu: &CPU) -> Option<usize> {
+        let pc = cpu.pc() as u16;
+        self.list.iter()
+            .filter(|bp| bp.enabled && bp.addr == pc)
+            .find(|bp| bp.condition.as_ref().is_none_or(|cond| cond.eval(cpu)))
+            .map(|bp| bp.id)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>3}  {:<8} {:>5}  hits {}", self.id, if self.enabled { "enabled" } else { "disabled" }, self.addr, self.hits)?;
+        if self.ignore_count > 0 {
+            write!(f, "  ignore {}", self.ignore_count)?;
+        }
+        if let Some(cond) = &self.condition {
+            write!(f, "  if {}", cond)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Basic blocks, per-function control-flow graphs and the call graph, built
+//! on top of a [`Disassembly`] and exportable as Graphviz DOT.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disasm::Disassembly;
+use crate::instruction::{Opcode, Operand};
+
+/// Why control moves from one block to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution runs off the end of the block into the next one.
+    Fallthrough,
+    /// An unconditional `JMP`.
+    Jump,
+    /// The edge taken by `JT`/`JF` when the tested value is non-zero.
+    True,
+    /// The edge taken by `JT`/`JF` when the tested value is zero.
+    False,
+}
+
+/// A control-flow edge out of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// First address of the block the edge leads to.
+    pub target: u16,
+    /// Why the edge is taken.
+    pub kind: EdgeKind,
+}
+
+/// A straight run of instructions entered only at the top and left only at
+/// the bottom. `CALL` does not end a block because the callee returns.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// Address of the first instruction.
+    pub start: u16,
+    /// One past the last word of the block.
+    pub end: u16,
+    /// Address of every instruction in the block, in order.
+    pub instructions: Vec<u16>,
+    /// Blocks control can continue into.
+    pub successors: Vec<Edge>,
+    /// Literal targets of the `CALL`s in the block.
+    pub calls: Vec<u16>,
+    /// The block contains a `CALL` through a register.
+    pub indirect_call: bool,
+    /// The block ends in `JMP` through a register.
+    pub indirect_jump: bool,
+}
+
+impl Block {
+    /// Address of the last instruction.
+    pub fn last(&self) -> u16 {
+        *self.instructions.last().expect("blocks are never empty")
+    }
+}
+
+/// The blocks reachable from a function's entry without following calls.
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// Address the function is called at.
+    pub entry: u16,
+    /// Start address of each of its blocks.
+    pub blocks: BTreeSet<u16>,
+    /// Functions called directly, or jumped to as a tail call.
+    pub callees: BTreeSet<u16>,
+    /// Some block calls through a register.
+    pub indirect_calls: bool,
+}
+
+/// Every block and function found in a program.
+pub struct Program {
+    /// Every block, keyed by start address.
+    pub blocks: BTreeMap<u16, Block>,
+    /// Every function, keyed by entry address.
+    pub functions: BTreeMap<u16, Function>,
+}
+
+impl Program {
+    /// Splits the disassembly into blocks and groups them into functions.
+    pub fn build(dis: &Disassembly) -> Program {
+        let mut leaders: BTreeSet<u16> = dis.functions.iter().chain(&dis.jump_targets).copied().collect();
+        for (&addr, ins) in &dis.instructions {
+            if matches!(ins.op, Opcode::Jmp | Opcode::Jt | Opcode::Jf | Opcode::Ret | Opcode::Halt) {
+                leaders.insert(addr + ins.len as u16);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|&&addr| dis.is_code(addr)) {
+            let mut block = Block {
+                start,
+                end: start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                calls: Vec::new(),
+                indirect_call: false,
+                indirect_jump: false,
+            };
+            let mut addr = start;
+            loop {
+                let ins = dis.instructions[&addr];
+                block.instructions.push(addr);
+                let next = addr + ins.len as u16;
+                block.end = next;
+                let target = |index: usize| match ins.operands[index] {
+                    Operand::Literal(target) => Some(target),
+                    Operand::Register(_) => None
+                };
+                match ins.op {
+                    Opcode::Halt | Opcode::Ret => break,
+                    Opcode::Jmp => {
+                        match target(0) {
+                            Some(target) => block.successors.push(Edge { target, kind: EdgeKind::Jump }),
+                            None => block.indirect_jump = true
+                        }
+                        break;
+                    },
+                    Opcode::Jt | Opcode::Jf => {
+                        let (taken, not_taken) = match ins.op {
+                            Opcode::Jt => (EdgeKind::True, EdgeKind::False),
+                            _ => (EdgeKind::False, EdgeKind::True)
+                        };
+                        if let Some(target) = target(1) {
+                            block.successors.push(Edge { target, kind: taken });
+                        }
+                        block.successors.push(Edge { target: next, kind: not_taken });
+                        break;
+                    },
+                    Opcode::Call => match target(0) {
+                        Some(target) => block.calls.push(target),
+                        None => block.indirect_call = true
+                    },
+                    _ => {}
+                }
+                if leaders.contains(&next) || !dis.is_code(next) {
+                    if dis.is_code(next) {
+                        block.successors.push(Edge { target: next, kind: EdgeKind::Fallthrough });
+                    }
+                    break;
+                }
+                addr = next;
+            }
+            blocks.insert(start, block);
+        }
+
+        let mut functions = BTreeMap::new();
+        for &entry in dis.functions.iter().filter(|&&addr| blocks.contains_key(&addr)) {
+            let mut function = Function { entry, blocks: BTreeSet::new(), callees: BTreeSet::new(), indirect_calls: false };
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                let block: &Block = match blocks.get(&start) {
+                    Some(block) => block,
+                    None => continue
+                };
+                if !function.blocks.insert(start) {
+                    continue;
+                }
+                function.callees.extend(&block.calls);
+                function.indirect_calls |= block.indirect_call;
+                for edge in &block.successors {
+                    if edge.target != entry && dis.functions.contains(&edge.target) {
+                        function.callees.insert(edge.target);
+                    } else {
+                        pending.push(edge.target);
+                    }
+                }
+            }
+            functions.insert(entry, function);
+        }
+        Program { blocks, functions }
+    }
+
+    /// The function's CFG as DOT: one box per block listing its instructions.
+    pub fn function_dot(&self, entry: u16, dis: &Disassembly) -> Option<String> {
+        let function = self.functions.get(&entry)?;
+        let mut out = String::new();
+        writeln!(out, "digraph fn_{} {{", entry).unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for &start in &function.blocks {
+            let block = &self.blocks[&start];
+            let mut label = String::new();
+            if let Some(name) = dis.label(start) {
+                write!(label, "{}:\\l", name).unwrap();
+            }
+            for addr in &block.instructions {
+                write!(label, "{:>5}  {}\\l", addr, escape(&dis.format_instruction(&dis.instructions[addr]))).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", start, label).unwrap();
+            for edge in &block.successors {
+                let attrs = match edge.kind {
+                    EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                    EdgeKind::True => " [label=\"true\", color=darkgreen]",
+                    EdgeKind::False => " [label=\"false\", color=red]",
+                };
+                if function.blocks.contains(&edge.target) {
+                    writeln!(out, "    b{} -> b{}{};", start, edge.target, attrs).unwrap();
+                } else if self.functions.contains_key(&edge.target) {
+                    writeln!(out, "    tail{} [label=\"fn_{}\", shape=ellipse];", edge.target, edge.target).unwrap();
+                    writeln!(out, "    b{} -> tail{} [style=dashed];", start, edge.target).unwrap();
+                }
+            }
+            if block.indirect_jump {
+                writeln!(out, "    jmp{} [label=\"?\", shape=ellipse];", start).unwrap();
+                writeln!(out, "    b{} -> jmp{} [style=dashed];", start, start).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        Some(out)
+    }
+
+    /// The whole-program call graph as DOT. Nodes show each function's entry
+    /// and size; functions making calls through a register get a dashed edge
+    /// to a `?` node.
+    pub fn call_graph_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph calls {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        let mut indirect = false;
+        for function in self.functions.values() {
+            let words: usize = function.blocks.iter()
+                .map(|start| (self.blocks[start].end - start) as usize)
+                .sum();
+            writeln!(out, "    f{} [label=\"fn_{}\\n{} blocks, {} words\"];", function.entry, function.entry, function.blocks.len(), words).unwrap();
+            for callee in &function.callees {
+                writeln!(out, "    f{} -> f{};", function.entry, callee).unwrap();
+            }
+            if function.indirect_calls {
+                indirect = true;
+                writeln!(out, "    f{} -> indirect [style=dashed];", function.entry).unwrap();
+            }
+        }
+        if indirect {
+            out.push_str("    indirect [label=\"?\", shape=ellipse];\n");
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,253 @@
+//! Which instructions a run executed, kept as bitmaps over the address space
+//! so that coverage from many runs can be saved and merged.
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::cfg::Program;
+use crate::cpu::{CPU, MEMORY_SIZE, Observer, StepResult};
+use crate::disasm::{self, Disassembly};
+
+/// Identifies a coverage file. Followed by a little-endian `u16` version.
+pub const MAGIC: &[u8; 8] = b"SYNCOVER";
+/// Format version this build reads and writes.
+pub const VERSION: u16 = 1;
+
+const WORDS: usize = MEMORY_SIZE / 64;
+
+/// Why a coverage file could not be read or written.
+#[derive(Debug)]
+pub enum CoverageError {
+    /// The file could not be read or written.
+    Io(io::Error),
+    /// The file does not start with [`MAGIC`].
+    BadMagic,
+    /// The file has a version this build does not read.
+    UnsupportedVersion(u16),
+    /// The file ends early or has extra bytes.
+    Truncated,
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoverageError::Io(err) => write!(f, "{}", err),
+            CoverageError::BadMagic => write!(f, "not a coverage file"),
+            CoverageError::UnsupportedVersion(version) => write!(f, "unsupported coverage file version {}", version),
+            CoverageError::Truncated => write!(f, "coverage file is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for CoverageError {}
+
+impl From<io::Error> for CoverageError {
+    fn from(err: io::Error) -> CoverageError {
+        CoverageError::Io(err)
+    }
+}
+
+/// A set of addresses, one bit each.
+#[derive(Clone, PartialEq, Eq)]
+struct Bitmap([u64; WORDS]);
+
+impl Bitmap {
+    fn insert(&mut self, addr: u16) {
+        self.0[addr as usize / 64] |= 1 << (addr % 64);
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        self.0[addr as usize / 64] & (1 << (addr % 64)) != 0
+    }
+
+    fn union(&mut self, other: &Bitmap) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..MEMORY_SIZE as u16).filter(|&addr| self.contains(addr))
+    }
+}
+
+/// Executed instruction addresses, plus every address entered by `CALL`,
+/// which finds functions only ever called through a register.
+#[derive(Clone)]
+pub struct Coverage {
+    executed: Bitmap,
+    called: Bitmap,
+    /// Address and call depth of the instruction about to run.
+    pc: Option<(u16, usize)>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    /// Coverage with nothing executed.
+    pub fn new() -> Coverage {
+        Coverage { executed: Bitmap([0; WORDS]), called: Bitmap([0; WORDS]), pc: None }
+    }
+
+    /// Whether an instruction starting at `addr` executed.
+    pub fn is_executed(&self, addr: u16) -> bool {
+        self.executed.contains(addr)
+    }
+
+    /// Number of distinct instruction addresses executed.
+    pub fn executed_count(&self) -> usize {
+        self.executed.len()
+    }
+
+    /// Addresses some `CALL` jumped to.
+    pub fn call_targets(&self) -> Vec<u16> {
+        self.called.iter().collect()
+    }
+
+    /// Adds everything `other` covered.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.executed.union(&other.executed);
+        self.called.union(&other.called);
+    }
+
+    /// `MAGIC`, `VERSION`, then the executed and called bitmaps as little-endian `u64`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        for word in self.executed.0.iter().chain(&self.called.0) {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    /// Parses the bytes written by [`Coverage::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Coverage, CoverageError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(CoverageError::BadMagic);
+        }
+        let version = match bytes.get(8..10) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]),
+            None => return Err(CoverageError::Truncated)
+        };
+        if version != VERSION {
+            return Err(CoverageError::UnsupportedVersion(version));
+        }
+        let body = &bytes[10..];
+        if body.len() != 2 * WORDS * 8 {
+            return Err(CoverageError::Truncated);
+        }
+        let mut coverage = Coverage::new();
+        let words = body.chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes")));
+        for (slot, word) in coverage.executed.0.iter_mut().chain(coverage.called.0.iter_mut()).zip(words) {
+            *slot = word;
+        }
+        Ok(coverage)
+    }
+
+    /// Writes the coverage to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CoverageError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Reads coverage written by [`Coverage::save`].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Coverage, CoverageError> {
+        Coverage::from_bytes(&fs::read(path)?)
+    }
+
+    /// Disassembles `memory` from `entries` and every recorded call target.
+    pub fn analyze(&self, memory: &[u16], entries: &[u16]) -> Disassembly {
+        let mut entries = entries.to_vec();
+        entries.extend(self.called.iter().filter(|&addr| (addr as usize) < memory.len()));
+        disasm::analyze(memory, &entries)
+    }
+
+    /// Summary counts followed by every function with its covered blocks,
+    /// least covered first.
+    pub fn report(&self, dis: &Disassembly, program: &Program) -> String {
+        let mut out = String::new();
+        let ratio = |covered: usize, total: usize| {
+            format!("{}/{} ({:.1}%)", covered, total, 100.0 * covered as f64 / total.max(1) as f64)
+        };
+        let instructions = dis.instructions.keys().filter(|&&addr| self.is_executed(addr)).count();
+        let blocks = program.blocks.keys().filter(|&&start| self.is_executed(start)).count();
+        let functions = program.functions.keys().filter(|&&entry| self.is_executed(entry)).count();
+        let outside = self.executed.iter().filter(|&addr| !dis.is_code(addr)).count();
+        writeln!(out, "instructions  {}", ratio(instructions, dis.instructions.len())).unwrap();
+        writeln!(out, "blocks        {}", ratio(blocks, program.blocks.len())).unwrap();
+        writeln!(out, "functions     {}", ratio(functions, program.functions.len())).unwrap();
+        if outside > 0 {
+            writeln!(out, "{} executed addresses are not in the static disassembly", outside).unwrap();
+        }
+
+        let mut rows: Vec<(u16, usize, usize)> = program.functions.values()
+            .map(|function| {
+                let covered = function.blocks.iter().filter(|&&start| self.is_executed(start)).count();
+                (function.entry, covered, function.blocks.len())
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            let (fa, fb) = (a.1 as f64 / a.2 as f64, b.1 as f64 / b.2 as f64);
+            fa.total_cmp(&fb).then(a.0.cmp(&b.0))
+        });
+        writeln!(out, "\n{:<10} {:<10} {:>6}  uncovered blocks", "function", "status", "blocks").unwrap();
+        for (entry, covered, total) in rows {
+            let status = match covered {
+                0 => "uncovered",
+                n if n == total => "covered",
+                _ => "partial"
+            };
+            let uncovered: Vec<String> = match covered {
+                0 => Vec::new(),
+                _ => program.functions[&entry].blocks.iter()
+                    .filter(|&&start| !self.is_executed(start))
+                    .map(|start| start.to_string())
+                    .collect()
+            };
+            let row = format!("{:<10} {:<10} {:>6}  {}", format!("fn_{}", entry), status, format!("{}/{}", covered, total), uncovered.join(" "));
+            writeln!(out, "{}", row.trim_end()).unwrap();
+        }
+        out
+    }
+
+    /// The disassembly with `+` beside executed instructions, `-` beside
+    /// instructions never executed and `!` beside data that was executed.
+    pub fn annotated_listing(&self, dis: &Disassembly, memory: &[u16]) -> String {
+        dis.annotated_listing(memory, &|addr| match (dis.is_code(addr), self.is_executed(addr)) {
+            (true, true) => '+',
+            (true, false) => '-',
+            (false, true) => '!',
+            (false, false) => ' ',
+        })
+    }
+}
+
+impl Observer for Coverage {
+    fn before_step(&mut self, cpu: &CPU) {
+        self.pc = match cpu.is_halted() {
+            true => None,
+            false => Some((cpu.pc() as u16, cpu.call_stack().len()))
+        };
+    }
+
+    fn after_step(&mut self, cpu: &CPU, result: StepResult) {
+        if matches!(result, StepResult::NeedsInput | StepResult::Fault(_)) {
+            return;
+        }
+        if let Some((pc, depth)) = self.pc.take() {
+            self.executed.insert(pc);
+            if cpu.call_stack().len() > depth {
+                self.called.insert(cpu.pc() as u16);
+            }
+        }
+    }
+}
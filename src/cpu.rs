@@ -1,456 +1,708 @@
-use std::fs::File;
-use std::io::Read;
-use std::io::stdin;
+//! The virtual machine itself.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
 use std::collections::VecDeque;
-use std::collections::HashMap;
+use crate::history::{History, StackChange, UndoRecord};
+use crate::hook::{HookContext, Hooks};
+use crate::instruction::{decode, DecodeError, Opcode, Operand};
+use crate::io::{InputSource, OutputSink, StdinInput, StdoutOutput};
+use crate::loader;
+use crate::patch::{Patch, PatchError, Patches};
+use crate::snapshot;
+use crate::snapshot::SaveState;
+use crate::watch::{Access, WatchHit, Watchpoints};
+
+/// Number of addressable words in the 15-bit address space.
+pub const MEMORY_SIZE: usize = 32768;
 
 const RUNNING:i32 = 100;
 const HALTED:i32 = 101;
 
-struct SaveState {
-    cpu: CPU,
-    cursor: usize
+/// Reasons the machine refuses to execute the instruction at the program counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The word at `addr` is not an opcode.
+    InvalidOpcode {
+        /// Address of the faulting instruction.
+        addr: u16,
+        /// The word found there.
+        opcode: u16,
+    },
+    /// An operand is neither a literal nor a register.
+    InvalidOperand {
+        /// Address of the faulting instruction.
+        addr: u16,
+        /// The operand word.
+        value: u16,
+    },
+    /// The instruction runs past the end of memory or accesses an address outside it.
+    AddressOutOfRange {
+        /// Address of the faulting instruction.
+        addr: u16,
+    },
+    /// `POP` with an empty stack.
+    StackUnderflow {
+        /// Address of the faulting instruction.
+        addr: u16,
+    },
+    /// `MOD` by zero.
+    DivisionByZero {
+        /// Address of the faulting instruction.
+        addr: u16,
+    },
+}
+
+impl From<DecodeError> for Fault {
+    fn from(err: DecodeError) -> Fault {
+        match err {
+            DecodeError::InvalidOpcode { addr, opcode } => Fault::InvalidOpcode { addr, opcode },
+            DecodeError::InvalidOperand { addr, value } => Fault::InvalidOperand { addr, value },
+            DecodeError::Truncated { addr } => Fault::AddressOutOfRange { addr },
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::InvalidOpcode { addr, opcode } => write!(f, "invalid operation {} at {}", opcode, addr),
+            Fault::InvalidOperand { addr, value } => write!(f, "invalid operand {} at {}", value, addr),
+            Fault::AddressOutOfRange { addr } => write!(f, "address out of range at {}", addr),
+            Fault::StackUnderflow { addr } => write!(f, "pop from empty stack at {}", addr),
+            Fault::DivisionByZero { addr } => write!(f, "mod by zero at {}", addr),
+        }
+    }
+}
+
+/// Outcome of executing a single instruction with [`CPU::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The instruction executed and the machine is ready for the next one.
+    Continued,
+    /// The machine is halted; the program counter no longer moves.
+    Halted,
+    /// `IN` found the input queue empty. Nothing was executed; push input and step again.
+    NeedsInput,
+    /// `OUT` executed and produced this character, which was also written to the output sink.
+    Output(char),
+    /// The instruction could not be executed. The program counter is left pointing at it.
+    Fault(Fault),
+}
+
+/// One entry of the shadow call stack kept alongside the guest stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the `CALL` instruction.
+    pub call_site: u16,
+    /// Address that was called.
+    pub function: u16,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.function, self.call_site)
+    }
+}
+
+/// Watches every instruction executed by [`CPU::run_with`].
+pub trait Observer {
+    /// Called with the machine about to execute the instruction at its program counter.
+    fn before_step(&mut self, _cpu: &CPU) {}
+
+    /// Called after each step. `NeedsInput` steps executed nothing and are
+    /// followed by another `before_step` for the same instruction.
+    fn after_step(&mut self, _cpu: &CPU, _result: StepResult) {}
 }
 
-#[derive(Clone)]
+/// The virtual machine: 32768 words of memory, eight registers, an unbounded
+/// stack and a queue of pending input characters. `IN` refills the queue from
+/// an [`InputSource`] and `OUT` writes to an [`OutputSink`]; both default to
+/// the terminal.
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     stack:          Vec<u16>,
     registers:      [u16; 8],
     memory:         Vec<u16>,
+    pc:             usize,
     state:          i32,
     input_queue:    VecDeque<u16>,
-    opcode_map:     HashMap<u16, String>
+    current_line:   String,
+    last_line:      String,
+    history:        Option<History>,
+    call_stack:     Vec<Frame>,
+    watchpoints:    Watchpoints,
+    watch_hits:     Vec<WatchHit>,
+    patches:        Patches,
+    hooks:          Hooks,
+    /// Hook run by the last step in place of a `CALL`.
+    hooked:         Option<u16>,
+    input:          Box<dyn InputSource>,
+    output:         Box<dyn OutputSink>
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
+    /// A machine reading from stdin and writing to stdout, with empty memory.
     pub fn new() -> CPU {
+        CPU::with_io(Box::new(StdinInput), Box::new(StdoutOutput))
+    }
+
+    /// A machine with empty memory using the given input source and output sink.
+    pub fn with_io(input: Box<dyn InputSource>, output: Box<dyn OutputSink>) -> CPU {
         CPU {
             stack: Vec::new(),
             registers: [0; 8],
-            memory: Vec::new(),
+            memory: vec![0; MEMORY_SIZE],
+            pc: 0,
             state: RUNNING,
             input_queue: VecDeque::new(),
-            opcode_map: HashMap::new()
+            current_line: String::new(),
+            last_line: String::new(),
+            history: None,
+            call_stack: Vec::new(),
+            watchpoints: Watchpoints::new(),
+            watch_hits: Vec::new(),
+            patches: Patches::new(),
+            hooks: Hooks::new(),
+            hooked: None,
+            input,
+            output
+        }
+    }
+
+    /// Replaces the source `IN` refills the input queue from.
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
+    }
+
+    /// Replaces the sink `OUT` writes to.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    /// Loads a program image from disk into memory at address 0.
+    pub fn read_binary<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        let binary = loader::read_binary(filename)?;
+        self.load(&binary);
+        Ok(())
+    }
+
+    /// Resets the machine and copies `program` into memory at address 0.
+    /// The input source and output sink are kept.
+    pub fn load(&mut self, program: &[u16]) {
+        self.stack.clear();
+        self.registers = [0; 8];
+        self.memory = vec![0; MEMORY_SIZE];
+        self.pc = 0;
+        self.state = RUNNING;
+        self.input_queue.clear();
+        self.current_line.clear();
+        self.last_line.clear();
+        self.call_stack.clear();
+        self.patches.clear();
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        let len = program.len().min(MEMORY_SIZE);
+        self.memory[..len].copy_from_slice(&program[..len]);
+    }
+
+    /// Address of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Moves the program counter without executing anything.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Whether the program has executed `HALT`.
+    pub fn is_halted(&self) -> bool {
+        self.state == HALTED
+    }
+
+    /// The eight registers, r0 first.
+    pub fn registers(&self) -> &[u16; 8] {
+        &self.registers
+    }
+
+    /// Sets register `reg` (0-7) without recording history.
+    pub fn set_register(&mut self, reg: usize, value: u16) {
+        self.registers[reg] = value;
+    }
+
+    /// All of memory, indexed by address.
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    /// Writable memory. Writes bypass watchpoints and history.
+    pub fn memory_mut(&mut self) -> &mut [u16] {
+        &mut self.memory
+    }
+
+    /// The guest stack, top last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Frames pushed by `CALL` and popped by `RET`, innermost last. Starts
+    /// empty after loading or restoring a snapshot.
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    /// The watchpoints checked as instructions access memory.
+    pub fn watchpoints(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    /// The hooks run in place of `CALL`s to their addresses.
+    pub fn hooks(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    /// The hooked address if the last step ran a hook instead of a `CALL`.
+    pub fn last_hook(&self) -> Option<u16> {
+        self.hooked
+    }
+
+    /// Writes `patch` over the loaded program. Loading another program or
+    /// restoring a snapshot forgets every patch.
+    pub fn apply_patch(&mut self, patch: Patch) -> Result<usize, PatchError> {
+        self.patches.apply(patch, &mut self.memory)
+    }
+
+    /// Puts back the words applied patch `id` replaced.
+    pub fn revert_patch(&mut self, id: usize) -> Result<(), String> {
+        self.patches.revert(id, &mut self.memory)
+    }
+
+    /// Writes a reverted patch over memory again.
+    pub fn reapply_patch(&mut self, id: usize) -> Result<(), String> {
+        self.patches.reapply(id, &mut self.memory)
+    }
+
+    /// Every patch applied since the program was loaded, reverted ones included.
+    pub fn patches(&self) -> &Patches {
+        &self.patches
+    }
+
+    /// Returns and clears the watchpoint hits recorded since the last call.
+    /// Hits are recorded while the instruction causing them executes.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// The last non-empty line the program has finished printing.
+    pub fn last_output_line(&self) -> &str {
+        &self.last_line
+    }
+
+    /// Captures the complete machine state.
+    pub fn snapshot(&self) -> SaveState {
+        SaveState {
+            memory: self.memory.clone(),
+            registers: self.registers,
+            stack: self.stack.clone(),
+            input_queue: self.input_queue.iter().copied().collect(),
+            pc: self.pc as u16,
+            halted: self.is_halted(),
+            saved_at: snapshot::now(),
+            last_output: self.last_line.clone()
+        }
+    }
+
+    /// Replaces the machine state with a previously captured snapshot.
+    pub fn restore(&mut self, state: &SaveState) {
+        self.memory = state.memory.clone();
+        self.memory.resize(MEMORY_SIZE, 0);
+        self.registers = state.registers;
+        self.stack = state.stack.clone();
+        self.input_queue = state.input_queue.iter().copied().collect();
+        self.pc = state.pc as usize;
+        self.state = if state.halted { HALTED } else { RUNNING };
+        self.current_line.clear();
+        self.last_line = state.last_output.clone();
+        self.call_stack.clear();
+        self.patches.clear();
+        if let Some(history) = &mut self.history {
+            history.clear();
         }
     }
 
-    pub fn read_binary(&mut self, filename: &String){
-        let mut binary: Vec<u16> = Vec::new();
-        self.opcode_map = HashMap::from([
-            (0, String::from("HALT")), // 0
-            (21, String::from("NOOP")), // 0,
-            (2, String::from("PUSH")), // 1
-            (3, String::from("POP")), // 1
-            (6, String::from("JMP")), // 1
-            (20, String::from("IN")), // 1
-            (19, String::from("OUT")), // 1
-            (17, String::from("CALL")), // 1
-            (7, String::from("JT")), // 2
-            (8, String::from("JF")), // 2
-            (15, String::from("RMEM")), // 2
-            (14, String::from("NOT")), // 2
-            (16, String::from("WMEM")), // 2
-            (1, String::from("SET")), // 2
-            (9, String::from("ADD")), // 3
-            (5, String::from("GT")), // 3
-            (4, String::from("EQ")), // 3
-            (10, String::from("MULT")), // 3
-            (11, String::from("MOD")), // 3
-            (12, String::from("AND")), // 3
-            (13, String::from("OR")), // 3
-            (18, String::from("RET")) // 1 from stack
-        ]);
-        let mut f =  File::open(&filename).expect("No file found");
-        let metadata = f.metadata().expect("Unable to read metadata");
-        let mut buffer = vec![0; metadata.len() as usize];
-        f.read(&mut buffer).expect("Buffer overflow");
-        
-        for x in (1..metadata.len() as usize).step_by(2) {
-            let low: u8 = buffer[x - 1];
-            let high: u8 = buffer[x];
-            //let byte: u16 = ((high << 8) | (low & 0xff)) as u16;
-            let byte: u16 = u16::from(high) << 8 | u16::from(low & 0xff);
-            binary.push(byte);
+    /// Starts recording an undo record per instruction, keeping at most
+    /// `budget` bytes of history. Changes made through `set_register` or
+    /// `memory_mut` are not recorded and survive stepping backwards.
+    pub fn enable_history(&mut self, budget: usize) {
+        self.history = Some(History::with_budget(budget));
+    }
+
+    /// Stops recording and drops the recorded history.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// The recorded history, if enabled.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Reverts the most recently executed instruction. Returns false when
+    /// there is no recorded history left. Output already written stays written.
+    pub fn step_back(&mut self) -> bool {
+        let record = match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(record) => record,
+            None => return false
+        };
+        if let Some((reg, value)) = record.register {
+            self.registers[reg as usize] = value;
+        }
+        if let Some((addr, value)) = record.memory {
+            self.memory[addr as usize] = value;
+        }
+        match record.stack {
+            StackChange::None => {},
+            StackChange::Pushed => {
+                self.stack.pop();
+            },
+            StackChange::Popped(value) => self.stack.push(value)
         }
+        if let Some(c) = record.input {
+            self.input_queue.push_front(c);
+        }
+        match decode(&self.memory, record.pc as usize).map(|ins| ins.op) {
+            Ok(Opcode::Call) => {
+                self.call_stack.pop();
+            },
+            Ok(Opcode::Ret) => self.call_stack.extend(record.frame),
+            _ => {}
+        }
+        self.pc = record.pc as usize;
+        self.state = RUNNING;
+        true
+    }
 
-        self.memory = binary;
+    /// Queues a line of text for the `IN` instruction.
+    pub fn push_input(&mut self, line: &str) {
+        for c in line.chars() {
+            self.input_queue.push_back(c as u16);
+        }
+    }
+
+    /// Executes exactly one instruction.
+    pub fn step(&mut self) -> StepResult {
+        if self.state == HALTED {
+            return StepResult::Halted;
+        }
+        let undo = match self.history {
+            Some(_) => self.undo_record(),
+            None => None
+        };
+        self.hooked = None;
+        let result = match self.execute() {
+            Ok(result) => result,
+            Err(fault) => StepResult::Fault(fault),
+        };
+        if let (Some(undo), Some(history)) = (undo, &mut self.history) {
+            // A hook may change anything, so there is no stepping back past one.
+            if self.hooked.is_some() {
+                history.clear();
+            } else if matches!(result, StepResult::Continued | StepResult::Output(_) | StepResult::Halted) {
+                history.push(undo);
+            }
+        }
+        result
     }
 
-    pub fn run(&mut self) {
-        let mut cursor: usize = 0;
-        let mut save_state: SaveState = SaveState {
-            cpu: self.clone(),
-            cursor: 0
+    /// Captures the values the instruction at the program counter is about to overwrite.
+    fn undo_record(&self) -> Option<UndoRecord> {
+        let ins = decode(&self.memory, self.pc).ok()?;
+        let mut record = UndoRecord {
+            pc: self.pc as u16,
+            register: None,
+            memory: None,
+            stack: StackChange::None,
+            input: None,
+            frame: None
         };
-        let mut debugging: bool = false;
-        let mut breakpoint: u16 = 0;
-        let mut stepping: bool = true;
-        while self.state == RUNNING {
-            let opcode: u16 = self.memory[cursor];
-
-            if debugging {
-                print!("\x1B[2J\x1B[1;1H");
-                println!("REGISTRY");
-                for i in 0..8 {
-                    let _r = self.registers[i];
-                    println!("R{}: {}", i, _r,);
+        let a = ins.operands[0];
+        match ins.op {
+            Opcode::Push | Opcode::Call => record.stack = StackChange::Pushed,
+            Opcode::Pop => record.stack = StackChange::Popped(*self.stack.last()?),
+            Opcode::Ret => {
+                if let Some(&top) = self.stack.last() {
+                    record.stack = StackChange::Popped(top);
+                    record.frame = self.call_stack.last().copied();
                 }
-                println!("STACK");
-                let s_len = self.stack.len();
-                let mut start = self.stack.len() as i32 - 5;
-                if start < 0 {
-                    start = 0;
+            },
+            Opcode::Wmem => {
+                let addr = self.value(a);
+                record.memory = Some((addr, *self.memory.get(addr as usize)?));
+            },
+            Opcode::In => record.input = Some(*self.input_queue.front()?),
+            _ => {}
+        }
+        if let (true, Operand::Register(reg)) = (ins.op.writes_register(), a) {
+            record.register = Some((reg, self.registers[reg as usize]));
+        }
+        Some(record)
+    }
+
+    fn execute(&mut self) -> Result<StepResult, Fault> {
+        let cursor = self.pc;
+        let ins = decode(&self.memory, cursor)?;
+        let [a, b, c] = ins.operands;
+        // An IN that has to wait for input runs again once it arrives.
+        if ins.op != Opcode::In || !self.input_queue.is_empty() {
+            self.watch(cursor as u16, Access::Execute, 0, 0);
+        }
+        let mut result = StepResult::Continued;
+        let mut next = cursor + ins.len;
+        match ins.op {
+            Opcode::Halt => {
+                self.state = HALTED;
+                return Ok(StepResult::Halted);
+            },
+            Opcode::Set => {
+                let b = self.value(b);
+                self.write_register(a, b)?;
+            },
+            Opcode::Push => {
+                let a = self.value(a);
+                self.stack.push(a);
+            },
+            Opcode::Pop => {
+                let top = match self.stack.last() {
+                    Some(&top) => top,
+                    None => return Err(Fault::StackUnderflow { addr: cursor as u16 })
+                };
+                self.write_register(a, top)?;
+                self.stack.pop();
+            },
+            Opcode::Eq => {
+                let value = self.value(b) == self.value(c);
+                self.write_register(a, value as u16)?;
+            },
+            Opcode::Gt => {
+                let value = self.value(b) > self.value(c);
+                self.write_register(a, value as u16)?;
+            },
+            Opcode::Jmp => {
+                next = self.value(a) as usize;
+            },
+            Opcode::Jt => {
+                if self.value(a) != 0 {
+                    next = self.value(b) as usize;
                 }
-                for i in start..s_len as i32 {
-                    let _s = self.stack[i as usize];
-                    println!("{}", _s);
+            },
+            Opcode::Jf => {
+                if self.value(a) == 0 {
+                    next = self.value(b) as usize;
                 }
-                println!();
-
-                for i in -3..7 {
-                    if (cursor as i32) + i < 0 {
-                        continue;
-                    }
-                    let mut p_str = String::new();
-                    let cur = (cursor as i32 + i) as usize;
-                    let _opcode = self.memory[cur];
-                    let mut a = 0;
-                    let mut b = 0;
-                    let mut c = 0;
-                    match _opcode {
-                        0 => {// HALT
-                            p_str = format!("{}     HALT", cur);
-                        },
-                        1 => {// SET
-                            a = self.memory[cur + 1];
-                            b = self.memory[cur + 2];
-                            p_str = format!("{}    SET {} to {}", cur, a, b);
-                        },
-                        2 => {// PUSH
-                            a = self.memory[cur + 1];
-                            p_str = format!("{}    PUSH {}", cur, a);
-                        },
-                        3 => {// POP
-                            a = self.memory[cur + 1];
-                            p_str = format!("{}   POP {}", cur, a);
-                        },
-                        4 => {// EQ
-                            a = self.memory[cur + 1];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   EQ ({} = {}) {}", cur, b, c, a);
-                        },
-                        5 => {// GT
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   GT ({} > {}) {}", cur, b, c, a);
-                        },
-                        6 => {// JMP
-                            a = self.memory[(cur + 1)];
-                            p_str = format!("{}   JMP {}", cur, a);
-                        },
-                        7 => {// JT
-                            a = self.memory[cur + 1];
-                            b = self.memory[(cur + 2)];
-                            p_str = format!("{}   JT {} JMP to {}", cur, a, b);
-                        },
-                        8 => {// JF
-                            a = self.memory[cur + 1];
-                            b = self.memory[cur + 2];
-                            p_str = format!("{}   JF {} JMP to {}", cur, a, b);
-                        },
-                        9 => {// ADD
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   ADD {} {} to {}", cur, b, c, a);
-                        },
-                        10 => {// MULT
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   MULT {} {} to {}", cur, b, c, a);
-                        },
-                        11 => {// MOD
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   MOD {} {} to {}", cur, b, c, a);
-                        },
-                        12 => {//AND
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   AND {} {} to {}", cur, b, c, a);
-                        },
-                        13 => {//OR
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            c = self.memory[(cur + 3)];
-                            p_str = format!("{}   OR {} {} to {}", cur, b, c, a);
-                        },
-                        14 => {//NOT
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            p_str = format!("{}   NOT {} {} to {}", cur, b, c, a);
-                        },
-                        15 => {//RMEM
-                            a = self.memory[cursor + 1];
-                            b = self.memory[cursor + 2];
-                            p_str = format!("{}   RMEM at {} to {}", cur, b, a);
-                        },
-                        16 => {//WMEM
-                            a = self.memory[(cur + 1)];
-                            b = self.memory[(cur + 2)];
-                            p_str = format!("{}   WMEM {} to {}", cur, b, a);
-                        }
-                        17 => {//CALL
-                            p_str = format!("{}   CALL {}", cur, cur + 2);
-                        },
-                        18 => {//RET
-                            p_str = format!("{}   RET", cur);
-                        }
-                        19 => {// OUT
-                            p_str = format!("{}   OUT", cur);
-                        },
-                        20 => {// IN
-                            p_str = format!("{}   IN", cur);
-                        },
-                        21 => { // NOOP
-                            p_str = format!("{}   NOOP", cur);
-                        },
-                        _ => {
-                            p_str = format!("{}   {}", cur, self.memory[cur]);
-                        }
-                    }
-                    if cur == cursor {
-                        p_str.insert(0, '[');
-                        p_str += "]";
-                        //println!("[{}    {}]:", cur, self.opcode_map[&opcode]);
-                    } /*else {
-                        println!("{}    {}:", cur, self.opcode_map[&opcode]);
-                    }*/
-                    println!("{}", p_str);
+            },
+            Opcode::Add => {
+                let sum = (self.value(b) as u32 + self.value(c) as u32) % 32768;
+                self.write_register(a, sum as u16)?;
+            },
+            Opcode::Mult => {
+                let product = (self.value(b) as u32 * self.value(c) as u32) % 32768;
+                self.write_register(a, product as u16)?;
+            },
+            Opcode::Mod => {
+                let c = self.value(c);
+                if c == 0 {
+                    return Err(Fault::DivisionByZero { addr: cursor as u16 });
                 }
-                println!();
-                println!("s: step   b {}: breakpoint   c: continue", breakpoint);
-                if breakpoint == cursor as u16 {
-                    stepping = true;
+                let b = self.value(b);
+                self.write_register(a, b % c)?;
+            },
+            Opcode::And => {
+                let value = self.value(b) & self.value(c);
+                self.write_register(a, value)?;
+            },
+            Opcode::Or => {
+                let value = self.value(b) | self.value(c);
+                self.write_register(a, value)?;
+            },
+            Opcode::Not => {
+                let value = !self.value(b) & 0x7fff;
+                self.write_register(a, value)?;
+            },
+            Opcode::Rmem => {
+                let b_addr = self.value(b) as usize;
+                match self.memory.get(b_addr) {
+                    Some(&value) => {
+                        self.write_register(a, value)?;
+                        self.watch(b_addr as u16, Access::Read, value, value);
+                    },
+                    None => return Err(Fault::AddressOutOfRange { addr: cursor as u16 })
                 }
-                if stepping {
-                    let mut buffer = String::new();
-                    let result = stdin().read_line(&mut buffer);
-                    if buffer.trim()[..1] == String::from("b") {
-                        breakpoint = buffer.trim()[2..buffer.trim().len()].parse::<u16>().unwrap();
-                        stepping = false;
-                    } else if buffer.trim() == "c" {
-                        stepping = false;
-                    }
+            },
+            Opcode::Wmem => {
+                let a_addr = self.value(a) as usize;
+                let b = self.value(b);
+                match self.memory.get_mut(a_addr) {
+                    Some(word) => {
+                        let old = std::mem::replace(word, b);
+                        self.watch(a_addr as u16, Access::Write, old, b);
+                    },
+                    None => return Err(Fault::AddressOutOfRange { addr: cursor as u16 })
                 }
-            }
-            match opcode {
-                0 => {// HALT
-                    self.state = HALTED;
-                },
-                1 => {// SET
-                    let b = self.read_value(cursor + 2);
-                    self.set_register(cursor + 1, b);
-                    cursor += 3;
-                },
-                2 => {// PUSH
-                    let a = self.read_value(cursor + 1);
-                    self.stack.push(a);
-                    cursor += 2;
-                },
-                3 => {// POP
-                    if self.stack.len() == 0 {
+            },
+            Opcode::Call => {
+                let target = self.value(a);
+                let handled = match self.hooks.get_mut(target) {
+                    Some(hook) => hook(&mut HookContext { registers: &mut self.registers, memory: &mut self.memory, stack: &mut self.stack }),
+                    None => false
+                };
+                if handled {
+                    self.hooked = Some(target);
+                } else {
+                    self.stack.push(next as u16);
+                    self.call_stack.push(Frame { call_site: cursor as u16, function: target });
+                    next = target as usize;
+                }
+            },
+            Opcode::Ret => {
+                match self.stack.pop() {
+                    Some(addr) => {
+                        self.call_stack.pop();
+                        next = addr as usize;
+                    },
+                    None => {
                         self.state = HALTED;
+                        return Ok(StepResult::Halted);
                     }
-
-                    let top = self.stack.pop().unwrap();
-                    self.set_register(cursor + 1, top);
-                    cursor += 2;
-                },
-                4 => {// EQ
-                    let b = self.read_value(cursor + 2);
-                    let c = self.read_value(cursor + 3);
-                    if b == c {
-                        self.set_register(cursor + 1, 1);
-                    } else {
-                        self.set_register(cursor + 1, 0)
-                    }
-                    cursor += 4;
-                },
-                5 => {// GT
-                    let b = self.read_value(cursor + 2);
-                    let c = self.read_value(cursor + 3);
-                    if b > c {
-                        self.set_register(cursor + 1, 1);
-                    } else {
-                        self.set_register(cursor + 1, 0)
-                    }
-                    cursor += 4;
-                },
-                6 => {// JMP
-                    cursor = self.read_value(cursor + 1) as usize;
-                },
-                7 => {// JT
-                    if self.read_value(cursor + 1) != 0 {
-                        cursor = self.read_value(cursor + 2) as usize;
-                    }
-                    else {
-                        cursor += 3;
-                    }
-                },
-                8 => {// JF
-                    if self.read_value(cursor + 1) == 0 {
-                        cursor = self.read_value(cursor + 2) as usize;
-                    }
-                    else {
-                        cursor += 3;
+                }
+            },
+            Opcode::Out => {
+                let c = (self.value(a) as u8) as char;
+                self.output.write_char(c);
+                if c == '\n' {
+                    if !self.current_line.trim().is_empty() {
+                        self.last_line = std::mem::take(&mut self.current_line);
                     }
-                },
-                9 => {// ADD
-                    let b = self.read_value(cursor + 2) as i32;
-                    let c = self.read_value(cursor + 3) as i32;
-                    let sum = (b + c) % 32768;
-                    self.set_register(cursor + 1, sum as u16);
-                    cursor += 4;
-                },
-                10 => {// MULT
-                    let b = self.read_value(cursor + 2) as i32;
-                    let c = self.read_value(cursor + 3) as i32;
-                    let sum = b * c;
-                    self.set_register(cursor + 1, (sum % 32768) as u16);
-                    cursor += 4;
-                },
-                11 => {// MOD
-                    let b = self.read_value(cursor + 2) as i32;
-                    let c = self.read_value(cursor + 3) as i32;
-                    let sum = b % c;
-                    self.set_register(cursor + 1, sum as u16);
-                    cursor += 4;
-                },
-                12 => {//AND
-                    let b = self.read_value(cursor + 2) as i32;
-                    let c = self.read_value(cursor + 3) as i32;
-                    self.set_register(cursor + 1, (b & c) as u16);
-                    cursor += 4;
-                },
-                13 => {//OR
-                    let b = self.read_value(cursor + 2) as i32;
-                    let c = self.read_value(cursor + 3) as i32;
-                    self.set_register(cursor + 1, (b | c) as u16);
-                    cursor += 4;
-                },
-                14 => {//NOT
-                    let b = self.read_value(cursor + 2) as i32;
-                    self.set_register(cursor + 1, ((!b) & 0x7fff) as u16);
-                    cursor += 3;
-                },
-                15 => {//RMEM
-                    let b_addr = self.read_value(cursor + 2);
-                    let b = self.memory[b_addr as usize];
-                    self.set_register(cursor + 1, b);
-                    cursor += 3;
-                },
-                16 => {//WMEM
-                    let a = self.read_value(cursor + 1);
-                    let b = self.read_value(cursor + 2);
-                    self.memory[a as usize] = b;
-                    cursor += 3;
+                    self.current_line.clear();
+                } else {
+                    self.current_line.push(c);
                 }
-                17 => {//CALL
-                    self.stack.push((cursor + 2) as u16);
-                    cursor = self.read_value(cursor + 1) as usize;
-                },
-                18 => {//RET
-                    cursor = self.stack.pop().unwrap() as usize;
+                result = StepResult::Output(c);
+            },
+            Opcode::In => {
+                if let Operand::Literal(value) = a {
+                    return Err(Fault::InvalidOperand { addr: cursor as u16, value });
                 }
-                19 => {// OUT
-                    print!("{}", (self.read_value(cursor + 1) as u8) as char);
-                    cursor += 2;
-                },
-                20 => {// IN
-                    if self.input_queue.len() == 0 {
-                        let mut buffer = String::new();
-                        let result = stdin().read_line(&mut buffer);
-                        
-                        if buffer.trim() == "save" {
-                            println!("saving state...");
-                            save_state.cpu = self.clone();
-                            save_state.cursor = cursor;
-                            println!("saved state");
-                        } else if buffer.trim() == "load" {
-                            println!("loading state...");
-                            *self = save_state.cpu.clone();
-                            cursor = save_state.cursor;
-                            println!("loaded state");
-                        } else if buffer.trim() == "d" {
-                            debugging = !debugging;
-                        } else if buffer.trim().len() > 3 && buffer.trim()[..3] == String::from("set") {
-                            let reg = buffer.trim()[4..5].parse::<usize>().unwrap();
-                            let val = buffer.trim()[6..buffer.trim().len()].parse::<u16>().unwrap();
-                            self.registers[reg] = val;
-                            println!("set reg {} to {}", reg, val);
-                        } else if buffer.trim() == "reg" {
-                            println!("register");
-                            for i in 0..8 {
-                                println!("{}: {}", i, self.registers[i]);
-                            }
-                        } else if buffer.trim() == "q" {
-                            std::process::exit(0);
-                        } else if buffer.trim() == "s" {
-                            stepping = true;
-                        }
-
-                        for c in buffer.chars() {
-                            let _c = c as u16;
-                            self.input_queue.push_back(_c);
-                        }
-                    } else {
-                        let c = self.input_queue.pop_front().unwrap();
-                        self.set_register(cursor + 1, c);
-                        cursor += 2;
+                match self.input_queue.pop_front() {
+                    Some(c) => self.write_register(a, c)?,
+                    None => {
+                        return Ok(StepResult::NeedsInput);
                     }
-                },
-                21 => { // NOOP
-                    cursor += 1;
-                },
-                _ =>  {
-                    println!("invalid operation {} at {}", self.memory[cursor], cursor);
-                    self.state = HALTED;
                 }
+            },
+            Opcode::Noop => {},
+        }
+        self.pc = next;
+        Ok(result)
+    }
+
+    /// Flushes the output sink and reads the next line from the input source,
+    /// echoing it to the output if the source asks for that.
+    pub fn read_input_line(&mut self) -> Option<String> {
+        self.output.flush();
+        let line = self.input.read_line()?;
+        if self.input.echo() {
+            for c in line.chars() {
+                self.output.write_char(c);
             }
         }
-        println!("Program halted, now exiting");
-    } 
+        Some(line)
+    }
 
-    fn get_register(&mut self, cursor: usize) -> usize {
-        return 32775 - self.memory[cursor] as usize;
+    /// Moves the next line from the input source into the input queue.
+    /// Returns false when the source is exhausted.
+    pub fn fill_input(&mut self) -> bool {
+        match self.read_input_line() {
+            Some(line) => {
+                self.push_input(&line);
+                true
+            },
+            None => false
+        }
     }
 
-    fn set_register(&mut self, cursor: usize, value: u16) {
-        let reg: usize = self.get_register(cursor);
-        self.registers[reg] = value;
+    /// Runs until the program halts or faults, refilling the input queue from
+    /// the input source whenever it runs dry. Returns the step result that
+    /// stopped the machine; `NeedsInput` means the input source was exhausted.
+    pub fn run(&mut self) -> StepResult {
+        self.run_with(&mut [])
     }
 
-    fn is_register(&mut self, cursor: usize) -> bool {
-        return cursor >= 32768 && cursor <= 32775;
+    /// Like [`CPU::run`], reporting every step to each of `observers`.
+    pub fn run_with(&mut self, observers: &mut [&mut dyn Observer]) -> StepResult {
+        let result = loop {
+            for observer in observers.iter_mut() {
+                observer.before_step(self);
+            }
+            let result = self.step();
+            for observer in observers.iter_mut() {
+                observer.after_step(self, result);
+            }
+            match result {
+                StepResult::Continued | StepResult::Output(_) => {},
+                StepResult::NeedsInput => {
+                    if !self.fill_input() {
+                        break StepResult::NeedsInput;
+                    }
+                },
+                result => break result
+            }
+        };
+        self.output.flush();
+        result
     }
 
-    fn read_value(&mut self, cursor: usize) -> u16 {
-        let value = self.memory[cursor] as usize;
+    /// Records a hit for every watchpoint covering the access.
+    fn watch(&mut self, addr: u16, access: Access, old: u16, new: u16) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        for (id, stop) in self.watchpoints.trigger(addr, access) {
+            self.watch_hits.push(WatchHit {
+                id,
+                access,
+                addr,
+                old,
+                new,
+                pc: self.pc as u16,
+                call_stack: self.call_stack.clone(),
+                stop
+            });
+        }
+    }
 
-        if self.is_register(value) {
-            return self.read_register(cursor);
+    fn value(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Literal(value) => value,
+            Operand::Register(reg) => self.registers[reg as usize],
         }
-        return value as u16;
     }
 
-    fn read_register(&mut self, cursor: usize) -> u16 {
-        let reg: usize = self.get_register(cursor);
-        return self.registers[reg];
+    fn write_register(&mut self, operand: Operand, value: u16) -> Result<(), Fault> {
+        match operand {
+            Operand::Register(reg) => {
+                self.registers[reg as usize] = value;
+                Ok(())
+            },
+            Operand::Literal(literal) => Err(Fault::InvalidOperand { addr: self.pc as u16, value: literal })
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,574 @@
+//! The interactive debugger wrapped around a running program.
+
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use crate::breakpoint::{parse_number, parse_register, Breakpoints, Condition};
+use crate::cpu::{CPU, Fault, MEMORY_SIZE, StepResult};
+use crate::diff::SnapshotDiff;
+use crate::solve::{solve_teleporter, TeleporterCheck};
+use crate::instruction::decode;
+use crate::io::{InputSource, StdinInput};
+use crate::snapshot::{format_timestamp, SaveSlots};
+use crate::watch::Access;
+
+/// Directory holding the save slots unless [`Debugger::set_save_dir`] says otherwise.
+pub const DEFAULT_SAVE_DIR: &str = "saves";
+/// Slot used by `save` and `load` without a name.
+pub const DEFAULT_SLOT: &str = "quicksave";
+
+/// What a line typed at the game prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameLine<'a> {
+    /// Text for the program; `!!text` arrives as `!text`.
+    Input(&'a str),
+    /// A lone `!`: open the console.
+    Console,
+    /// `!command`: run one console command.
+    Command(&'a str),
+}
+
+impl GameLine<'_> {
+    /// Sorts a line read at the game prompt; a leading `!` escapes to the console.
+    pub fn classify(line: &str) -> GameLine<'_> {
+        match line.strip_prefix(ESCAPE) {
+            Some(rest) if rest.starts_with(ESCAPE) => GameLine::Input(rest),
+            Some(rest) if rest.trim().is_empty() => GameLine::Console,
+            Some(rest) => GameLine::Command(rest.trim()),
+            None => GameLine::Input(line)
+        }
+    }
+}
+
+/// Why [`Debugger::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The program executed `HALT`.
+    Halted,
+    /// The program faulted.
+    Fault(Fault),
+    /// The program wanted input and none was left.
+    InputClosed,
+    /// The user quit from the console.
+    Quit
+}
+
+/// Lines typed at the game prompt that start with this character go to the
+/// debugger instead of the program.
+pub const ESCAPE: char = '!';
+
+/// What the console should do after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Stay at the console prompt.
+    Stay,
+    /// Execute one instruction and come back to the console.
+    Step,
+    /// Leave the console and let the program run.
+    Continue,
+    Quit
+}
+
+/// Interactive front-end around [`CPU`]. Game input reaches the program
+/// untouched except for lines starting with [`ESCAPE`]: a lone `!` opens the
+/// debugger console, `!<command>` runs a single console command and `!!...`
+/// sends the rest of the line, starting with `!`, to the program. The console
+/// has its own `(debug)` prompt, read from stdin by default, so its commands never end up
+/// in the program's input. Breakpoints and stopping watchpoints also open it.
+pub struct Debugger {
+    slots: SaveSlots,
+    /// Show the full register/stack/listing view at the console prompt.
+    visual: bool,
+    breakpoints: Breakpoints,
+    /// The console is open; execution continues only when a command says so.
+    stopped: bool,
+    /// Where console commands are read from.
+    console: Box<dyn InputSource>
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// A debugger with no breakpoints, saving to [`DEFAULT_SAVE_DIR`].
+    pub fn new() -> Debugger {
+        Debugger {
+            slots: SaveSlots::new(DEFAULT_SAVE_DIR),
+            visual: false,
+            breakpoints: Breakpoints::new(),
+            stopped: false,
+            console: Box::new(StdinInput)
+        }
+    }
+
+    /// Keeps save slots in `dir`.
+    pub fn set_save_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.slots = SaveSlots::new(dir);
+    }
+
+    /// Reads console commands from `input` instead of stdin.
+    pub fn set_console_input(&mut self, input: Box<dyn InputSource>) {
+        self.console = input;
+    }
+
+    /// The breakpoint table.
+    pub fn breakpoints(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Opens the console before the first instruction runs.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Runs the machine until it halts, faults, input is closed or the user quits.
+    pub fn run(&mut self, cpu: &mut CPU) -> Exit {
+        // Set while an instruction waits for input, so retrying it does not
+        // count as reaching its breakpoints again.
+        let mut waiting_at = None;
+        loop {
+            if waiting_at != Some(cpu.pc()) {
+                if let Some(id) = self.breakpoints.check(cpu) {
+                    self.stopped = true;
+                    println!("stopped at breakpoint {}", id);
+                }
+                // Execute watchpoints that break stop before the instruction
+                // runs; the hit itself is still counted when it executes.
+                let pc = cpu.pc() as u16;
+                if let Some(watch) = cpu.watchpoints().iter().find(|watch| watch.stop && watch.triggers(pc, Access::Execute)) {
+                    self.stopped = true;
+                    println!("stopped at watchpoint {}: execute {}", watch.id, pc);
+                }
+            }
+            if self.stopped && self.console(cpu) == Action::Quit {
+                return Exit::Quit;
+            }
+            let result = cpu.step();
+            waiting_at = match result {
+                StepResult::NeedsInput => Some(cpu.pc()),
+                _ => None
+            };
+            for hit in cpu.take_watch_hits() {
+                if hit.stop && hit.access == Access::Execute {
+                    continue;
+                }
+                println!("{}", hit);
+                if hit.stop {
+                    self.stopped = true;
+                }
+            }
+            match result {
+                StepResult::Continued | StepResult::Output(_) => {},
+                StepResult::NeedsInput => {
+                    let line = match cpu.read_input_line() {
+                        Some(line) => line,
+                        None => return Exit::InputClosed
+                    };
+                    match GameLine::classify(&line) {
+                        GameLine::Input(text) => cpu.push_input(text),
+                        GameLine::Console => self.stopped = true,
+                        GameLine::Command(command) => match self.command(cpu, command) {
+                            Action::Quit => return Exit::Quit,
+                            Action::Step => self.stopped = true,
+                            Action::Stay | Action::Continue => {}
+                        }
+                    }
+                },
+                StepResult::Halted => return Exit::Halted,
+                StepResult::Fault(fault) => return Exit::Fault(fault)
+            }
+        }
+    }
+
+    /// Reads console commands until one resumes execution. Closing the
+    /// console input leaves the console and lets the program run.
+    fn console(&mut self, cpu: &mut CPU) -> Action {
+        loop {
+            if self.visual {
+                print_debug_view(cpu, &self.breakpoints);
+            } else {
+                print_current(cpu);
+            }
+            print!("(debug) ");
+            let _ = stdout().flush();
+            let buffer = match self.console.read_line() {
+                Some(line) => line,
+                None => {
+                    self.stopped = false;
+                    return Action::Continue;
+                }
+            };
+            let line = buffer.trim();
+            let action = if line.is_empty() { Action::Step } else { self.command(cpu, line) };
+            match action {
+                Action::Stay => {},
+                Action::Step | Action::Quit => return action,
+                Action::Continue => {
+                    self.stopped = false;
+                    return action;
+                }
+            }
+        }
+    }
+
+    /// Runs one console command.
+    fn command(&mut self, cpu: &mut CPU, line: &str) -> Action {
+        if self.breakpoint_command(line) || self.watch_command(cpu, line) {
+            return Action::Stay;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["s"] | ["step"] => return Action::Step,
+            ["c"] | ["continue"] => return Action::Continue,
+            ["q"] | ["quit"] => return Action::Quit,
+            ["rs"] | ["reverse-step"] => {
+                if !cpu.step_back() {
+                    println!("no history to step back into");
+                }
+            },
+            ["rc"] | ["reverse-continue"] => self.reverse_continue(cpu),
+            ["rewind", count] => match count.parse::<usize>() {
+                Ok(count) => {
+                    let undone = (0..count).take_while(|_| cpu.step_back()).count();
+                    if undone < count {
+                        println!("history ran out after {} instructions", undone);
+                    }
+                },
+                Err(_) => println!("usage: rewind <count>")
+            },
+            ["save"] => self.save(cpu, DEFAULT_SLOT),
+            ["save", name] => self.save(cpu, name),
+            ["load"] => self.load(cpu, DEFAULT_SLOT),
+            ["load", name] => self.load(cpu, name),
+            ["saves"] => self.list_saves(),
+            ["patches"] => {
+                if cpu.patches().is_empty() {
+                    println!("no patches");
+                }
+                for patch in cpu.patches().iter() {
+                    println!("{}", patch);
+                }
+            },
+            ["revert", id] | ["reapply", id] => {
+                let result = match id.parse::<usize>() {
+                    Ok(id) if words[0] == "revert" => cpu.revert_patch(id),
+                    Ok(id) => cpu.reapply_patch(id),
+                    Err(_) => Err(format!("bad patch id '{}'", id))
+                };
+                if let Err(err) = result {
+                    println!("{}", err);
+                }
+            },
+            ["hooks"] => {
+                if cpu.hooks().is_empty() {
+                    println!("no hooks");
+                }
+                let state = if cpu.hooks().is_enabled() { "enabled" } else { "disabled" };
+                for (addr, name) in cpu.hooks().iter() {
+                    println!("{:>5}  {}  {}", addr, name, state);
+                }
+            },
+            ["hooks", "on"] | ["hooks", "off"] => cpu.hooks().set_enabled(words[1] == "on"),
+            ["solve", "teleporter"] => solve(cpu, self.console.as_mut()),
+            ["diff"] => self.diff(cpu, DEFAULT_SLOT),
+            ["diff", name] => self.diff(cpu, name),
+            ["delsave", name] => match self.slots.delete(name) {
+                Ok(()) => println!("deleted {}", name),
+                Err(err) => println!("could not delete {}: {}", name, err)
+            },
+            ["d"] => self.visual = !self.visual,
+            ["set", reg, val] => {
+                let reg = parse_register(reg).map(usize::from).or_else(|| reg.parse::<usize>().ok().filter(|&reg| reg < 8));
+                match (reg, parse_number(val)) {
+                    (Some(_), Ok(val)) if val as usize >= MEMORY_SIZE => println!("{} is not a 15-bit value", val),
+                    (Some(reg), Ok(val)) => {
+                        cpu.set_register(reg, val);
+                        println!("set reg {} to {}", reg, val);
+                    },
+                    _ => println!("usage: set <r0-r7> <value>")
+                }
+            },
+            ["reg"] => {
+                println!("register");
+                for (i, value) in cpu.registers().iter().enumerate() {
+                    println!("{}: {}", i, value);
+                }
+            },
+            ["help"] => print_help(),
+            _ => println!("unknown command '{}', try help", line)
+        }
+        Action::Stay
+    }
+
+    /// Steps backwards until a breakpoint matches or history runs out.
+    fn reverse_continue(&mut self, cpu: &mut CPU) {
+        if !cpu.step_back() {
+            println!("no history to step back into");
+            return;
+        }
+        while self.breakpoints.matches(cpu).is_none() {
+            if !cpu.step_back() {
+                println!("history ran out before reaching a breakpoint");
+                return;
+            }
+        }
+    }
+
+    /// Handles `b <addr> [if <cond>]`, `bl`, `delete`, `enable`, `disable`,
+    /// `ignore` and `cond`. Returns false if `line` is not a breakpoint command.
+    fn breakpoint_command(&mut self, line: &str) -> bool {
+        let (head, condition) = match line.split_once(" if ") {
+            Some((head, condition)) => (head, Some(condition)),
+            None => (line, None)
+        };
+        let words: Vec<&str> = head.split_whitespace().collect();
+        let id = |word: &str| word.parse::<usize>().map_err(|_| format!("bad breakpoint id '{}'", word));
+        let result: Result<(), String> = match words.as_slice() {
+            ["b", addr] => parse_number(addr).and_then(|addr| {
+                let condition = condition.map(Condition::parse).transpose()?;
+                let id = self.breakpoints.add(addr, condition);
+                println!("breakpoint {} at {}", id, addr);
+                Ok(())
+            }),
+            ["bl"] => {
+                if self.breakpoints.is_empty() {
+                    println!("no breakpoints");
+                }
+                for bp in self.breakpoints.iter() {
+                    println!("{}", bp);
+                }
+                Ok(())
+            },
+            ["delete", n] => id(n).and_then(|n| match self.breakpoints.remove(n) {
+                true => Ok(()),
+                false => Err(format!("no breakpoint {}", n))
+            }),
+            ["enable", n] | ["disable", n] => id(n).and_then(|n| match self.breakpoints.get_mut(n) {
+                Some(bp) => {
+                    bp.enabled = words[0] == "enable";
+                    Ok(())
+                },
+                None => Err(format!("no breakpoint {}", n))
+            }),
+            ["ignore", n, count] => id(n).and_then(|n| {
+                let count = count.parse::<u64>().map_err(|_| format!("bad count '{}'", count))?;
+                let bp = self.breakpoints.get_mut(n).ok_or(format!("no breakpoint {}", n))?;
+                bp.ignore_count = count;
+                Ok(())
+            }),
+            ["cond", n, rest @ ..] => id(n).and_then(|n| {
+                let source = rest.join(" ");
+                let condition = if source.is_empty() { None } else { Some(Condition::parse(&source)?) };
+                let bp = self.breakpoints.get_mut(n).ok_or(format!("no breakpoint {}", n))?;
+                bp.condition = condition;
+                Ok(())
+            }),
+            _ => return false
+        };
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+        true
+    }
+
+    /// Handles `watch <addr>[..<end>] [r|w|x...] [break]`, `wl` and `unwatch <id>`.
+    /// Returns false if `line` is not a watchpoint command.
+    fn watch_command(&mut self, cpu: &mut CPU, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let watchpoints = cpu.watchpoints();
+        let result: Result<(), String> = match words.as_slice() {
+            ["watch", range, options @ ..] => (|| {
+                let (start, end) = match range.split_once("..") {
+                    Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+                    None => (parse_number(range)?, parse_number(range)?)
+                };
+                if end < start {
+                    return Err(format!("empty range {}", range));
+                }
+                let mut kinds = "w";
+                let mut stop = false;
+                for option in options {
+                    match *option {
+                        "break" => stop = true,
+                        kind if !kind.is_empty() && kind.chars().all(|c| "rwx".contains(c)) => kinds = kind,
+                        other => return Err(format!("unknown watch option '{}'", other))
+                    }
+                }
+                let id = watchpoints.add(start, end, kinds.contains('r'), kinds.contains('w'), kinds.contains('x'), stop);
+                println!("watchpoint {} on {}", id, range);
+                Ok(())
+            })(),
+            ["wl"] => {
+                if watchpoints.is_empty() {
+                    println!("no watchpoints");
+                }
+                for watch in watchpoints.iter() {
+                    println!("{}", watch);
+                }
+                Ok(())
+            },
+            ["unwatch", n] => match n.parse::<usize>() {
+                Ok(n) if watchpoints.remove(n) => Ok(()),
+                _ => Err(format!("no watchpoint {}", n))
+            },
+            _ => return false
+        };
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+        true
+    }
+
+    fn save(&self, cpu: &CPU, name: &str) {
+        println!("saving state...");
+        match self.slots.save(name, &cpu.snapshot()) {
+            Ok(()) => println!("saved state to {}", name),
+            Err(err) => println!("could not save {}: {}", name, err)
+        }
+    }
+
+    fn load(&self, cpu: &mut CPU, name: &str) {
+        println!("loading state...");
+        match self.slots.load(name) {
+            Ok(state) => {
+                cpu.restore(&state);
+                println!("loaded state from {}", name);
+            },
+            Err(err) => println!("could not load {}: {}", name, err)
+        }
+    }
+
+    /// Prints what changed between the named save and the machine now.
+    fn diff(&self, cpu: &CPU, name: &str) {
+        match self.slots.load(name) {
+            Ok(state) => print!("{}", SnapshotDiff::new(&state, &cpu.snapshot())),
+            Err(err) => println!("could not load {}: {}", name, err)
+        }
+    }
+
+    fn list_saves(&self) {
+        match self.slots.list() {
+            Ok(slots) if slots.is_empty() => println!("no saves in {}", self.slots.dir().display()),
+            Ok(slots) => {
+                println!("{:<16} {:<19} {:>5}  last output", "name", "saved (UTC)", "pc");
+                for slot in slots {
+                    println!("{:<16} {:<19} {:>5}  {}", slot.name, format_timestamp(slot.saved_at), slot.pc, slot.last_output);
+                }
+            },
+            Err(err) => println!("could not list saves: {}", err)
+        }
+    }
+}
+
+fn print_debug_view(cpu: &CPU, breakpoints: &Breakpoints) {
+    let cursor = cpu.pc();
+    let memory = cpu.memory();
+    print!("\x1B[2J\x1B[1;1H");
+    println!("REGISTRY");
+    for (i, value) in cpu.registers().iter().enumerate() {
+        println!("R{}: {}", i, value);
+    }
+    println!("STACK");
+    let stack = cpu.stack();
+    for s in &stack[stack.len().saturating_sub(5)..] {
+        println!("{}", s);
+    }
+    println!();
+
+    let mut cur = listing_start(memory, cursor);
+    for _ in 0..10 {
+        if cur >= memory.len() {
+            break;
+        }
+        let (text, len) = match decode(memory, cur) {
+            Ok(ins) => (ins.to_string(), ins.len),
+            Err(_) => (memory[cur].to_string(), 1)
+        };
+        let mark = if breakpoints.iter().any(|bp| bp.enabled && bp.addr as usize == cur) { "*" } else { "" };
+        if cur == cursor {
+            println!("{}[{}   {}]", mark, cur, text);
+        } else {
+            println!("{}{}   {}", mark, cur, text);
+        }
+        cur += len;
+    }
+}
+
+/// One line: the instruction about to run.
+fn print_current(cpu: &CPU) {
+    let text = match decode(cpu.memory(), cpu.pc()) {
+        Ok(ins) => ins.to_string(),
+        Err(err) => err.to_string()
+    };
+    println!("{:>5}   {}", cpu.pc(), text);
+}
+
+/// Finds the r7 value the teleporter wants and offers to set it and patch
+/// out the check.
+fn solve(cpu: &mut CPU, console: &mut dyn InputSource) {
+    let check = match TeleporterCheck::find(cpu.memory()) {
+        Some(check) => check,
+        None => {
+            println!("no teleporter check in memory");
+            return;
+        }
+    };
+    println!("check at {}: fn_{}({}, {}) must return {}; trying every r7...", check.call_site, check.function, check.m, check.n, check.expected);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let r7 = match solve_teleporter(&check, 0..=32767, threads).first() {
+        Some(&r7) => r7,
+        None => {
+            println!("no value of r7 passes the check");
+            return;
+        }
+    };
+    println!("r7 = {}", r7);
+    print!("set r7 to {} and patch out the check at {}? [y/N] ", r7, check.call_site);
+    let _ = stdout().flush();
+    let answer = console.read_line().unwrap_or_default();
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return;
+    }
+    cpu.set_register(7, r7);
+    let patch = check.patch(cpu.memory());
+    match cpu.apply_patch(patch) {
+        Ok(id) => println!("set r7 to {}, patch {} applied", r7, id),
+        Err(err) => println!("could not patch: {}", err.message)
+    }
+}
+
+fn print_help() {
+    println!("s, <enter>: step   c: continue   q: quit   d: toggle full view   reg   set <reg> <value>");
+    println!("b <addr> [if <cond>]   bl   delete|enable|disable <id>   ignore <id> <n>   cond <id> <cond>");
+    println!("watch <addr>[..<end>] [rwx] [break]   wl   unwatch <id>");
+    println!("rs, reverse-step   rc, reverse-continue   rewind <n>");
+    println!("save [name]   load [name]   saves   delsave <name>   diff [name]: changes since a save");
+    println!("patches   revert <id>   reapply <id>   hooks [on|off]   solve teleporter");
+    println!("at the game prompt: ! opens this console, !<command> runs one command, !!text sends !text");
+}
+
+/// Finds an address up to three instructions before `cursor` from which
+/// decoding lands exactly on `cursor`, so the listing stays aligned.
+fn listing_start(memory: &[u16], cursor: usize) -> usize {
+    let mut best = cursor;
+    let mut best_count = 0;
+    for start in cursor.saturating_sub(12)..cursor {
+        let mut cur = start;
+        let mut count = 0;
+        while cur < cursor {
+            cur += instruction_len(memory, cur);
+            count += 1;
+        }
+        if cur == cursor && count <= 3 && count > best_count {
+            best = start;
+            best_count = count;
+        }
+    }
+    best
+}
+
+fn instruction_len(memory: &[u16], addr: usize) -> usize {
+    decode(memory, addr).map(|ins| ins.len).unwrap_or(1)
+}
@@ -0,0 +1,589 @@
+//! Turns a function's control-flow graph into C-like pseudo-code.
+//!
+//! Conditional branches become `if`/`else` joined at their immediate
+//! post-dominator, natural loops become `while (1)` with `break` and
+//! `continue`, and anything that does not fit falls back to `goto`. Stack
+//! slots become locals when the stack depth at every instruction is known,
+//! and registers saved on entry and restored before every `return` are
+//! dropped and listed in the header instead. Arithmetic is modulo 32768.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::cfg::{Block, EdgeKind, Function, Program};
+use crate::disasm::Disassembly;
+use crate::instruction::{Instruction, Opcode, Operand};
+
+/// Column where address comments start.
+const COMMENT_COLUMN: usize = 44;
+
+/// Pseudo-code for every function in the program, in address order.
+pub fn decompile(program: &Program, dis: &Disassembly) -> String {
+    let mut out = String::new();
+    for &entry in program.functions.keys() {
+        if let Some(text) = decompile_function(program, dis, entry) {
+            out.push_str(&text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Pseudo-code for the function starting at `entry`.
+pub fn decompile_function(program: &Program, dis: &Disassembly, entry: u16) -> Option<String> {
+    let function = program.functions.get(&entry)?;
+    let mut decompiler = Decompiler::new(program, dis, function);
+    decompiler.region(entry, None);
+    Some(decompiler.finish())
+}
+
+struct Loop {
+    header: u16,
+    body: BTreeSet<u16>,
+    exit: Option<u16>,
+}
+
+struct Decompiler<'a> {
+    program: &'a Program,
+    dis: &'a Disassembly,
+    function: &'a Function,
+    /// Successors inside the function, per block.
+    succs: BTreeMap<u16, Vec<u16>>,
+    ipdom: BTreeMap<u16, u16>,
+    /// Natural loop bodies by header.
+    loop_bodies: BTreeMap<u16, BTreeSet<u16>>,
+    /// Stack depth before each instruction, if it is consistent.
+    depth: Option<BTreeMap<u16, usize>>,
+    /// Prologue `PUSH`es and epilogue `POP`s that only save and restore registers.
+    skipped: BTreeSet<u16>,
+    saved: Vec<u8>,
+    emitted: BTreeSet<u16>,
+    loops: Vec<Loop>,
+    gotos: BTreeSet<u16>,
+    lines: Vec<Line>,
+    indent: usize,
+}
+
+enum Line {
+    Code { indent: usize, text: String, addr: Option<u16> },
+    Label(u16),
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(program: &'a Program, dis: &'a Disassembly, function: &'a Function) -> Decompiler<'a> {
+        let succs = function.blocks.iter()
+            .map(|&start| {
+                let targets = program.blocks[&start].successors.iter()
+                    .map(|edge| edge.target)
+                    .filter(|target| function.blocks.contains(target))
+                    .collect();
+                (start, targets)
+            })
+            .collect();
+        let mut decompiler = Decompiler {
+            program,
+            dis,
+            function,
+            succs,
+            ipdom: BTreeMap::new(),
+            loop_bodies: BTreeMap::new(),
+            depth: None,
+            skipped: BTreeSet::new(),
+            saved: Vec::new(),
+            emitted: BTreeSet::new(),
+            loops: Vec::new(),
+            gotos: BTreeSet::new(),
+            lines: Vec::new(),
+            indent: 1,
+        };
+        decompiler.find_loops();
+        decompiler.ipdom = decompiler.post_dominators();
+        decompiler.depth = decompiler.stack_depths();
+        decompiler.find_saved_registers();
+        decompiler
+    }
+
+    fn block(&self, start: u16) -> &'a Block {
+        &self.program.blocks[&start]
+    }
+
+    fn ins(&self, addr: u16) -> Instruction {
+        self.dis.instructions[&addr]
+    }
+
+    /// Natural loops: for every back edge `b -> h` where `h` dominates `b`,
+    /// the blocks that reach `b` without passing through `h`.
+    fn find_loops(&mut self) {
+        let dominators = self.dominators();
+        let mut preds: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+        for (&from, targets) in &self.succs {
+            for &to in targets {
+                preds.entry(to).or_default().push(from);
+            }
+        }
+        for (&from, targets) in &self.succs {
+            for &header in targets {
+                if !dominators[&from].contains(&header) {
+                    continue;
+                }
+                let body = self.loop_bodies.entry(header).or_insert_with(|| BTreeSet::from([header]));
+                let mut pending = vec![from];
+                while let Some(node) = pending.pop() {
+                    if body.insert(node) {
+                        pending.extend(preds.get(&node).into_iter().flatten());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Dominator sets, by the classic iterative data-flow algorithm.
+    fn dominators(&self) -> BTreeMap<u16, BTreeSet<u16>> {
+        let entry = self.function.entry;
+        let all = &self.function.blocks;
+        let mut preds: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+        for (&from, targets) in &self.succs {
+            for &to in targets {
+                preds.entry(to).or_default().push(from);
+            }
+        }
+        let mut dom: BTreeMap<u16, BTreeSet<u16>> = all.iter()
+            .map(|&b| (b, if b == entry { BTreeSet::from([b]) } else { all.clone() }))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in all.iter().filter(|&&b| b != entry) {
+                let mut set = intersect(preds.get(&b).into_iter().flatten().map(|p| &dom[p]));
+                set.insert(b);
+                if set != dom[&b] {
+                    dom.insert(b, set);
+                    changed = true;
+                }
+            }
+        }
+        dom
+    }
+
+    /// Immediate post-dominators. Blocks without successors inside the
+    /// function all lead to a virtual exit, which is left out of the result.
+    fn post_dominators(&self) -> BTreeMap<u16, u16> {
+        let all = &self.function.blocks;
+        let mut pdom: BTreeMap<u16, BTreeSet<u16>> = all.iter()
+            .map(|&b| (b, if self.succs[&b].is_empty() { BTreeSet::from([b]) } else { all.clone() }))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in all.iter().rev().filter(|&&b| !self.succs[&b].is_empty()) {
+                let mut set = intersect(self.succs[&b].iter().map(|s| &pdom[s]));
+                set.insert(b);
+                if set != pdom[&b] {
+                    pdom.insert(b, set);
+                    changed = true;
+                }
+            }
+        }
+        // The immediate post-dominator is the strict post-dominator that all
+        // the others post-dominate, i.e. the one with the largest set.
+        pdom.iter()
+            .filter_map(|(&b, set)| {
+                set.iter()
+                    .filter(|&&p| p != b)
+                    .max_by_key(|p| pdom[p].len())
+                    .map(|&p| (b, p))
+            })
+            .collect()
+    }
+
+    /// Stack depth before every instruction, or `None` if two paths disagree
+    /// or the function pops more than it pushed.
+    fn stack_depths(&self) -> Option<BTreeMap<u16, usize>> {
+        let mut depth = BTreeMap::new();
+        let mut at_block: BTreeMap<u16, usize> = BTreeMap::from([(self.function.entry, 0)]);
+        let mut pending = vec![self.function.entry];
+        while let Some(start) = pending.pop() {
+            let mut d = at_block[&start];
+            for &addr in &self.block(start).instructions {
+                depth.insert(addr, d);
+                match self.ins(addr).op {
+                    Opcode::Push => d += 1,
+                    Opcode::Pop => d = d.checked_sub(1)?,
+                    _ => {}
+                }
+            }
+            for &next in &self.succs[&start] {
+                match at_block.get(&next) {
+                    Some(&known) if known != d => return None,
+                    Some(_) => {},
+                    None => {
+                        at_block.insert(next, d);
+                        pending.push(next);
+                    }
+                }
+            }
+        }
+        Some(depth)
+    }
+
+    /// Registers pushed at the very start and popped in reverse right before
+    /// every `RET`.
+    fn find_saved_registers(&mut self) {
+        let entry = self.block(self.function.entry);
+        let prologue: Vec<(u16, u8)> = entry.instructions.iter()
+            .map_while(|&addr| match self.ins(addr) {
+                Instruction { op: Opcode::Push, operands: [Operand::Register(reg), ..], .. } => Some((addr, reg)),
+                _ => None
+            })
+            .collect();
+        if prologue.is_empty() {
+            return;
+        }
+        let mut skipped: BTreeSet<u16> = prologue.iter().map(|&(addr, _)| addr).collect();
+        let mut returns = 0;
+        for &start in &self.function.blocks {
+            let block = self.block(start);
+            let last = block.last();
+            if self.ins(last).op != Opcode::Ret {
+                continue;
+            }
+            returns += 1;
+            let body = &block.instructions[..block.instructions.len() - 1];
+            if body.len() < prologue.len() || (start == entry.start && body.len() < 2 * prologue.len()) {
+                return;
+            }
+            let epilogue = &body[body.len() - prologue.len()..];
+            for (&addr, &(_, reg)) in epilogue.iter().zip(prologue.iter().rev()) {
+                match self.ins(addr) {
+                    Instruction { op: Opcode::Pop, operands: [Operand::Register(r), ..], .. } if r == reg => skipped.insert(addr),
+                    _ => return
+                };
+            }
+        }
+        if returns > 0 {
+            self.saved = prologue.iter().map(|&(_, reg)| reg).collect();
+            self.skipped = skipped;
+        }
+    }
+
+    /// Emits blocks starting at `start` until control reaches `stop`.
+    fn region(&mut self, start: u16, stop: Option<u16>) {
+        let mut cur = start;
+        loop {
+            if Some(cur) == stop {
+                return;
+            }
+            if !self.function.blocks.contains(&cur) {
+                self.line(&format!("fn_{}();", cur), None);
+                self.line("return;  // tail call", None);
+                return;
+            }
+            if let Some(ctx) = self.loops.last() {
+                if cur == ctx.header {
+                    self.line("continue;", None);
+                    return;
+                }
+                if Some(cur) == ctx.exit {
+                    self.line("break;", None);
+                    return;
+                }
+            }
+            if self.emitted.contains(&cur) || self.loops.iter().any(|ctx| ctx.header == cur || ctx.exit == Some(cur)) {
+                self.gotos.insert(cur);
+                self.line(&format!("goto L_{};", cur), None);
+                return;
+            }
+            self.emitted.insert(cur);
+            let next = match self.loop_bodies.get(&cur) {
+                Some(body) => {
+                    let exit = self.loop_exit(cur, body);
+                    self.loops.push(Loop { header: cur, body: body.clone(), exit });
+                    self.lines.push(Line::Label(cur));
+                    match self.while_condition(cur, exit) {
+                        Some((cond, inside)) => {
+                            self.line(&format!("while ({}) {{", cond), Some(self.block(cur).last()));
+                            self.indent += 1;
+                            self.region(inside, None);
+                        },
+                        None => {
+                            self.line("while (1) {", None);
+                            self.indent += 1;
+                            if let Some(next) = self.step(cur, None) {
+                                self.region(next, None);
+                            }
+                        }
+                    }
+                    // Falling off the end of the body already continues.
+                    if matches!(self.lines.last(), Some(Line::Code { indent, text, .. }) if *indent == self.indent && text == "continue;") {
+                        self.lines.pop();
+                    }
+                    self.indent -= 1;
+                    self.line("}", None);
+                    self.loops.pop();
+                    exit
+                },
+                None => {
+                    self.lines.push(Line::Label(cur));
+                    self.step(cur, stop)
+                }
+            };
+            match next {
+                Some(next) => cur = next,
+                None => return
+            }
+        }
+    }
+
+    /// Where a loop continues once it is left: the header's post-dominator if
+    /// that lies outside the loop, otherwise the lowest exit target.
+    fn loop_exit(&self, header: u16, body: &BTreeSet<u16>) -> Option<u16> {
+        if let Some(&p) = self.ipdom.get(&header).filter(|p| !body.contains(p)) {
+            return Some(p);
+        }
+        body.iter()
+            .flat_map(|b| self.succs[b].iter())
+            .filter(|target| !body.contains(target))
+            .min()
+            .copied()
+    }
+
+    /// For a loop whose header is nothing but a `JT`/`JF` leaving the loop,
+    /// the condition to stay in it and the block the body starts at.
+    fn while_condition(&self, header: u16, exit: Option<u16>) -> Option<(String, u16)> {
+        let block = self.block(header);
+        let ins = self.ins(block.last());
+        if block.instructions.len() != 1 || !matches!(ins.op, Opcode::Jt | Opcode::Jf) {
+            return None;
+        }
+        let edge = |kind| block.successors.iter().find(|e| e.kind == kind).map(|e| e.target);
+        let (when_true, when_false) = (edge(EdgeKind::True)?, edge(EdgeKind::False)?);
+        let test = self.operand(ins.operands[0]);
+        if Some(when_false) == exit && when_true != header {
+            Some((test, when_true))
+        } else if Some(when_true) == exit && when_false != header {
+            Some((format!("!{}", test), when_false))
+        } else {
+            None
+        }
+    }
+
+    /// Emits one block and the `if` it may end in. Returns the block to
+    /// continue with, if any.
+    fn step(&mut self, start: u16, stop: Option<u16>) -> Option<u16> {
+        let block = self.block(start);
+        let body_len = match self.ins(block.last()).op {
+            Opcode::Jmp | Opcode::Jt | Opcode::Jf => block.instructions.len() - 1,
+            _ => block.instructions.len()
+        };
+        self.statements(&block.instructions[..body_len]);
+        let last = self.ins(block.last());
+        let next = block.end;
+        match last.op {
+            Opcode::Halt | Opcode::Ret => None,
+            Opcode::Jmp => match last.operands[0] {
+                Operand::Literal(target) if self.function.blocks.contains(&target) => Some(target),
+                Operand::Literal(target) => {
+                    self.line(&format!("fn_{}();", target), Some(block.last()));
+                    self.line("return;  // tail call", None);
+                    None
+                },
+                Operand::Register(reg) => {
+                    self.line(&format!("goto *r{};", reg), Some(block.last()));
+                    None
+                }
+            },
+            Opcode::Jt | Opcode::Jf => {
+                let test = self.operand(last.operands[0]);
+                let (when_true, when_false) = match (block.successors.iter().find(|e| e.kind == EdgeKind::True), block.successors.iter().find(|e| e.kind == EdgeKind::False)) {
+                    (Some(t), Some(f)) => (t.target, f.target),
+                    _ => {
+                        // A jump through a register: only the fall-through is known.
+                        let target = self.operand(last.operands[1]);
+                        let cond = if last.op == Opcode::Jt { test } else { format!("!{}", test) };
+                        self.line(&format!("if ({}) goto *{};", cond, target), Some(block.last()));
+                        return Some(next);
+                    }
+                };
+                let mut merge = self.ipdom.get(&start).copied();
+                if let Some(ctx) = self.loops.last() {
+                    if merge.is_some_and(|m| !ctx.body.contains(&m) && Some(m) != ctx.exit) {
+                        merge = None;
+                    }
+                }
+                let merge = merge.or(stop);
+                self.branch(&test, when_true, when_false, merge, block.last());
+                merge.filter(|&m| Some(m) != stop)
+            },
+            _ => block.successors.first().map(|_| next)
+        }
+    }
+
+    fn branch(&mut self, test: &str, when_true: u16, when_false: u16, merge: Option<u16>, addr: u16) {
+        let (cond, first, second) = if Some(when_true) == merge {
+            (format!("!{}", test), when_false, None)
+        } else if Some(when_false) == merge {
+            (test.to_string(), when_true, None)
+        } else {
+            (test.to_string(), when_true, Some(when_false))
+        };
+        self.line(&format!("if ({}) {{", cond), Some(addr));
+        self.indent += 1;
+        self.region(first, merge);
+        self.indent -= 1;
+        if let Some(second) = second {
+            self.line("} else {", None);
+            self.indent += 1;
+            self.region(second, merge);
+            self.indent -= 1;
+        }
+        self.line("}", None);
+    }
+
+    fn statements(&mut self, addrs: &[u16]) {
+        let mut i = 0;
+        while i < addrs.len() {
+            let addr = addrs[i];
+            i += 1;
+            if self.skipped.contains(&addr) {
+                continue;
+            }
+            let ins = self.ins(addr);
+            let [a, b, c] = ins.operands;
+            let text = match ins.op {
+                Opcode::Noop => continue,
+                Opcode::Halt => String::from("halt();"),
+                Opcode::Ret => String::from("return;"),
+                Opcode::Set => format!("{} = {};", self.operand(a), self.operand(b)),
+                Opcode::Push => match self.local(addr, 0) {
+                    Some(local) => format!("{} = {};", local, self.operand(a)),
+                    None => format!("push({});", self.operand(a))
+                },
+                Opcode::Pop => match self.local(addr, 1) {
+                    Some(local) => format!("{} = {};", self.operand(a), local),
+                    None => format!("{} = pop();", self.operand(a))
+                },
+                Opcode::Eq => format!("{} = {} == {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Gt => format!("{} = {} > {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Add => format!("{} = {} + {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Mult => format!("{} = {} * {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Mod => format!("{} = {} % {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::And => format!("{} = {} & {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Or => format!("{} = {} | {};", self.operand(a), self.operand(b), self.operand(c)),
+                Opcode::Not => format!("{} = ~{};", self.operand(a), self.operand(b)),
+                Opcode::Rmem => format!("{} = mem[{}];", self.operand(a), self.operand(b)),
+                Opcode::Wmem => format!("mem[{}] = {};", self.operand(a), self.operand(b)),
+                Opcode::Call => match a {
+                    Operand::Literal(target) => format!("fn_{}();", target),
+                    Operand::Register(reg) => format!("(*r{})();", reg)
+                },
+                Opcode::Out => match a {
+                    Operand::Literal(value) => {
+                        let mut text = String::new();
+                        push_char(&mut text, value);
+                        while let Some(&next) = addrs.get(i) {
+                            match self.ins(next) {
+                                Instruction { op: Opcode::Out, operands: [Operand::Literal(value), ..], .. } => push_char(&mut text, value),
+                                _ => break
+                            }
+                            i += 1;
+                        }
+                        format!("print(\"{}\");", text)
+                    },
+                    Operand::Register(reg) => format!("putchar(r{});", reg)
+                },
+                Opcode::In => format!("{} = getchar();", self.operand(a)),
+                Opcode::Jmp | Opcode::Jt | Opcode::Jf => continue
+            };
+            self.line(&text, Some(addr));
+        }
+    }
+
+    /// The local standing for the stack slot a `PUSH` (offset 0) writes or a
+    /// `POP` (offset 1) reads.
+    fn local(&self, addr: u16, offset: usize) -> Option<String> {
+        let depth = *self.depth.as_ref()?.get(&addr)?;
+        Some(format!("local{}", depth.checked_sub(offset + self.saved.len())?))
+    }
+
+    fn operand(&self, operand: Operand) -> String {
+        match operand {
+            Operand::Literal(value) => value.to_string(),
+            Operand::Register(reg) => format!("r{}", reg),
+        }
+    }
+
+    fn line(&mut self, text: &str, addr: Option<u16>) {
+        self.lines.push(Line::Code { indent: self.indent, text: text.to_string(), addr });
+    }
+
+    /// Renders the collected lines, keeping only labels some `goto` uses.
+    fn finish(self) -> String {
+        let entry = self.function.entry;
+        let mut reads = BTreeSet::new();
+        let mut writes = BTreeSet::new();
+        for &start in &self.function.blocks {
+            for &addr in &self.block(start).instructions {
+                if self.skipped.contains(&addr) {
+                    continue;
+                }
+                let ins = self.ins(addr);
+                for (i, arg) in ins.args().iter().enumerate() {
+                    if let Operand::Register(reg) = arg {
+                        if i == 0 && ins.op.writes_register() {
+                            writes.insert(*reg);
+                        } else {
+                            reads.insert(*reg);
+                        }
+                    }
+                }
+            }
+        }
+        let regs = |set: &BTreeSet<u8>| set.iter().map(|reg| format!("r{}", reg)).collect::<Vec<_>>().join(" ");
+        let mut out = String::new();
+        writeln!(out, "// fn_{}: reads {}, writes {}", entry, or_none(regs(&reads)), or_none(regs(&writes))).unwrap();
+        if !self.saved.is_empty() {
+            let saved: BTreeSet<u8> = self.saved.iter().copied().collect();
+            writeln!(out, "// preserves {}", regs(&saved)).unwrap();
+        }
+        if self.depth.is_none() {
+            writeln!(out, "// stack depth differs between paths; push/pop kept").unwrap();
+        }
+        writeln!(out, "void fn_{}() {{", entry).unwrap();
+        for line in &self.lines {
+            match line {
+                Line::Label(addr) if self.gotos.contains(addr) => writeln!(out, "L_{}:", addr).unwrap(),
+                Line::Label(_) => {},
+                Line::Code { indent, text, addr } => {
+                    let code = format!("{}{}", "    ".repeat(*indent), text);
+                    match addr {
+                        Some(addr) => writeln!(out, "{:<width$} // {}", code, addr, width = COMMENT_COLUMN).unwrap(),
+                        None => writeln!(out, "{}", code).unwrap()
+                    }
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn intersect<'s>(mut sets: impl Iterator<Item = &'s BTreeSet<u16>>) -> BTreeSet<u16> {
+    let first = match sets.next() {
+        Some(first) => first.clone(),
+        None => return BTreeSet::new()
+    };
+    sets.fold(first, |acc, set| acc.intersection(set).copied().collect())
+}
+
+fn or_none(text: String) -> String {
+    if text.is_empty() { String::from("nothing") } else { text }
+}
+
+fn push_char(text: &mut String, value: u16) {
+    match (value as u8) as char {
+        '\n' => text.push_str("\\n"),
+        '"' => text.push_str("\\\""),
+        '\\' => text.push_str("\\\\"),
+        c if (' '..='~').contains(&c) => text.push(c),
+        c => write!(text, "\\x{:02x}", c as u32).unwrap()
+    }
+}
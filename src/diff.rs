@@ -0,0 +1,136 @@
+//! Differences between machine snapshots, used to find where the program
+//! keeps a piece of state by comparing saves taken before and after it changes.
+
+use std::fmt;
+use crate::snapshot::SaveState;
+
+/// A run of consecutive memory words that all differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryChange {
+    /// First address of the run.
+    pub start: u16,
+    /// The words in the first snapshot.
+    pub before: Vec<u16>,
+    /// The words in the second snapshot.
+    pub after: Vec<u16>,
+}
+
+impl MemoryChange {
+    /// Last address of the run.
+    pub fn end(&self) -> u16 {
+        self.start + self.before.len() as u16 - 1
+    }
+}
+
+/// Stacks compared from the bottom: both share `common` entries, after which
+/// `removed` was only in the first and `added` only in the second.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StackDiff {
+    /// Entries both stacks share, counted from the bottom.
+    pub common: usize,
+    /// Entries only the first stack has above `common`.
+    pub removed: Vec<u16>,
+    /// Entries only the second stack has above `common`.
+    pub added: Vec<u16>,
+}
+
+/// Everything that differs between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// Old and new program counter, if it moved.
+    pub pc: Option<(u16, u16)>,
+    /// Register number, old value, new value.
+    pub registers: Vec<(u8, u16, u16)>,
+    /// Changed memory, in address order.
+    pub memory: Vec<MemoryChange>,
+    /// How the stacks differ.
+    pub stack: StackDiff,
+}
+
+impl SnapshotDiff {
+    /// Compares `before` with `after`.
+    pub fn new(before: &SaveState, after: &SaveState) -> SnapshotDiff {
+        let registers = (0..8u8)
+            .filter(|&r| before.registers[r as usize] != after.registers[r as usize])
+            .map(|r| (r, before.registers[r as usize], after.registers[r as usize]))
+            .collect();
+
+        let mut memory: Vec<MemoryChange> = Vec::new();
+        for addr in 0..before.memory.len().max(after.memory.len()) {
+            let (old, new) = (word(&before.memory, addr), word(&after.memory, addr));
+            if old == new {
+                continue;
+            }
+            match memory.last_mut() {
+                Some(change) if change.end() as usize + 1 == addr => {
+                    change.before.push(old);
+                    change.after.push(new);
+                },
+                _ => memory.push(MemoryChange { start: addr as u16, before: vec![old], after: vec![new] })
+            }
+        }
+
+        let common = before.stack.iter().zip(&after.stack).take_while(|(a, b)| a == b).count();
+        let stack = StackDiff { common, removed: before.stack[common..].to_vec(), added: after.stack[common..].to_vec() };
+        let pc = (before.pc != after.pc).then_some((before.pc, after.pc));
+        SnapshotDiff { pc, registers, memory, stack }
+    }
+
+    /// Whether the snapshots are identical, save for timestamps and output.
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_none() && self.registers.is_empty() && self.memory.is_empty()
+            && self.stack.removed.is_empty() && self.stack.added.is_empty()
+    }
+
+    /// Number of memory words that differ.
+    pub fn changed_words(&self) -> usize {
+        self.memory.iter().map(|change| change.before.len()).sum()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        if let Some((old, new)) = self.pc {
+            writeln!(f, "pc  {} -> {}", old, new)?;
+        }
+        for &(reg, old, new) in &self.registers {
+            writeln!(f, "r{}  {} -> {}", reg, old, new)?;
+        }
+        if !self.stack.removed.is_empty() || !self.stack.added.is_empty() {
+            writeln!(f, "stack  {} in common, removed {:?}, added {:?}", self.stack.common, self.stack.removed, self.stack.added)?;
+        }
+        if !self.memory.is_empty() {
+            writeln!(f, "memory  {} words in {} ranges", self.changed_words(), self.memory.len())?;
+        }
+        for change in &self.memory {
+            match change.before.len() {
+                1 => writeln!(f, "  {}", change.start)?,
+                len => writeln!(f, "  {}..{} ({} words)", change.start, change.end(), len)?
+            }
+            for (i, (old, new)) in change.before.iter().zip(&change.after).enumerate() {
+                writeln!(f, "    {:>5}: {} -> {}", change.start as usize + i, old, new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn word(memory: &[u16], addr: usize) -> u16 {
+    memory.get(addr).copied().unwrap_or(0)
+}
+
+/// Memory words whose value changed exactly `times` times between consecutive
+/// snapshots of `states`, each with its value in every snapshot.
+pub fn changed_n_times(states: &[SaveState], times: usize) -> Vec<(u16, Vec<u16>)> {
+    let len = states.iter().map(|state| state.memory.len()).max().unwrap_or(0);
+    (0..len)
+        .filter_map(|addr| {
+            let values: Vec<u16> = states.iter().map(|state| word(&state.memory, addr)).collect();
+            let changes = values.windows(2).filter(|pair| pair[0] != pair[1]).count();
+            (changes == times).then_some((addr as u16, values))
+        })
+        .collect()
+}
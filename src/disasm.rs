@@ -0,0 +1,240 @@
+//! Recursive-descent disassembly.
+//!
+//! Decoding starts at the entry points and follows every statically known
+//! successor: fall-through, literal `JMP`/`JT`/`JF` targets and literal `CALL`
+//! targets. A jump or call through a register is followed when an earlier
+//! `SET <reg> <literal>` in the same straight-line run set it, and a literal
+//! `WMEM <addr> <value>` is applied before the code it rewrites is decoded,
+//! so the self-test's self-modifying jump is followed too. Words never
+//! reached that way are data; pass other register targets as extra entry points.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::instruction::{decode, Instruction, Opcode, Operand};
+
+/// Data words printed per `.data` line.
+const DATA_PER_LINE: usize = 8;
+
+/// The result of [`analyze`]: which words are code and what refers to them.
+pub struct Disassembly {
+    /// Every reachable instruction, keyed by address.
+    pub instructions: BTreeMap<u16, Instruction>,
+    /// Known `CALL` targets and the entry points.
+    pub functions: BTreeSet<u16>,
+    /// Known `JMP`/`JT`/`JF` targets.
+    pub jump_targets: BTreeSet<u16>,
+    len: usize,
+}
+
+impl Disassembly {
+    /// Label for an address that is a function or jump target.
+    pub fn label(&self, addr: u16) -> Option<String> {
+        if self.functions.contains(&addr) {
+            Some(format!("fn_{}", addr))
+        } else if self.jump_targets.contains(&addr) {
+            Some(format!("L{}", addr))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `addr` is the first word of a decoded instruction.
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.instructions.contains_key(&addr)
+    }
+
+    /// The text of one instruction, with jump and call targets shown as labels.
+    pub fn format_instruction(&self, ins: &Instruction) -> String {
+        let mut text = ins.op.to_string();
+        for (i, arg) in ins.args().iter().enumerate() {
+            let label = match (*arg, branch_target_index(ins.op)) {
+                (Operand::Literal(target), Some(index)) if index == i => self.label(target),
+                _ => None
+            };
+            match label {
+                Some(label) => write!(text, " {}", label).unwrap(),
+                None => write!(text, " {}", arg).unwrap()
+            }
+        }
+        text
+    }
+
+    /// The full listing: labels, instructions with runs of `OUT` collapsed into
+    /// strings, and everything else as `.data`.
+    pub fn listing(&self, memory: &[u16]) -> String {
+        self.listing_with(memory, None)
+    }
+
+    /// Like [`Disassembly::listing`], with `mark(addr)` printed in a margin
+    /// before every instruction and data line.
+    pub fn annotated_listing(&self, memory: &[u16], mark: &dyn Fn(u16) -> char) -> String {
+        self.listing_with(memory, Some(mark))
+    }
+
+    fn listing_with(&self, memory: &[u16], mark: Option<&dyn Fn(u16) -> char>) -> String {
+        let margin = |addr: usize| mark.map(|mark| format!("{} ", mark(addr as u16))).unwrap_or_default();
+        let mut out = String::new();
+        let mut addr = 0;
+        while addr < self.len {
+            let at = addr as u16;
+            if let Some(label) = self.label(at) {
+                if self.functions.contains(&at) && !out.is_empty() {
+                    out.push('\n');
+                }
+                writeln!(out, "{}:", label).unwrap();
+            }
+            match self.instructions.get(&at) {
+                Some(ins) => {
+                    let (text, len) = match self.out_run(at) {
+                        Some((text, len)) => (format!("OUT \"{}\"", escape(&text)), len),
+                        None => (self.format_instruction(ins), ins.len)
+                    };
+                    writeln!(out, "{}{:>5}  {}", margin(addr), addr, text).unwrap();
+                    addr += len;
+                },
+                None => {
+                    let mut end = addr + 1;
+                    while end < self.len && end - addr < DATA_PER_LINE && !self.is_code(end as u16) && self.label(end as u16).is_none() {
+                        end += 1;
+                    }
+                    let words = &memory[addr..end];
+                    let values: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+                    let ascii: String = words.iter()
+                        .map(|&word| match char::from_u32(word as u32) {
+                            Some(c) if (' '..='~').contains(&c) => c,
+                            _ => '.'
+                        })
+                        .collect();
+                    writeln!(out, "{}{:>5}  .data {:<48} ; {}", margin(addr), addr, values.join(", "), ascii).unwrap();
+                    addr = end;
+                }
+            }
+        }
+        out
+    }
+
+    /// A run of two or more `OUT <literal>` instructions starting at `addr`
+    /// with no label inside it, as the text printed and the words covered.
+    fn out_run(&self, addr: u16) -> Option<(String, usize)> {
+        let mut text = String::new();
+        let mut count = 0;
+        let mut cur = addr;
+        while let Some(ins) = self.instructions.get(&cur) {
+            match (ins.op, ins.operands[0]) {
+                (Opcode::Out, Operand::Literal(c)) if cur == addr || self.label(cur).is_none() => {
+                    text.push((c as u8) as char);
+                    count += 1;
+                    cur += ins.len as u16;
+                },
+                _ => break
+            }
+        }
+        match count {
+            0 | 1 => None,
+            n => Some((text, 2 * n))
+        }
+    }
+}
+
+/// Disassembles `memory`, following control flow from each of `entries`.
+pub fn analyze(memory: &[u16], entries: &[u16]) -> Disassembly {
+    let mut result = Disassembly {
+        instructions: BTreeMap::new(),
+        functions: entries.iter().copied().collect(),
+        jump_targets: BTreeSet::new(),
+        len: memory.len(),
+    };
+    // Literal WMEMs are applied to a copy, so code they rewrite decodes as it will run.
+    let mut memory = memory.to_vec();
+    // Each pending address carries the register values known on the way in,
+    // which only fall-through keeps.
+    let mut pending: Vec<(u16, Constants)> = entries.iter().map(|&entry| (entry, UNKNOWN)).collect();
+    while let Some((addr, mut known)) = pending.pop() {
+        if result.instructions.contains_key(&addr) {
+            continue;
+        }
+        let ins = match decode(&memory, addr as usize) {
+            Ok(ins) => ins,
+            Err(_) => continue
+        };
+        result.instructions.insert(addr, ins);
+        let next = addr + ins.len as u16;
+        let target = match branch_target_index(ins.op).map(|i| ins.operands[i]) {
+            Some(Operand::Literal(target)) => Some(target),
+            Some(Operand::Register(reg)) => known[reg as usize],
+            None => None
+        };
+        track(&mut known, &ins);
+        if let (Opcode::Wmem, [Operand::Literal(dest), Operand::Literal(value), _]) = (ins.op, ins.operands) {
+            if let Some(word) = memory.get_mut(dest as usize) {
+                *word = value;
+            }
+        }
+        match ins.op {
+            Opcode::Halt | Opcode::Ret => {},
+            Opcode::Jmp => {
+                result.jump_targets.extend(target);
+                pending.extend(target.map(|target| (target, UNKNOWN)));
+            },
+            Opcode::Call => {
+                result.functions.extend(target);
+                pending.extend(target.map(|target| (target, UNKNOWN)));
+                pending.push((next, UNKNOWN));
+            },
+            Opcode::Jt | Opcode::Jf => {
+                result.jump_targets.extend(target);
+                pending.extend(target.map(|target| (target, UNKNOWN)));
+                pending.push((next, known));
+            },
+            _ => pending.push((next, known))
+        }
+    }
+    result
+}
+
+/// Register values set from literals or copied between registers, where known.
+type Constants = [Option<u16>; 8];
+
+const UNKNOWN: Constants = [None; 8];
+
+/// Updates `known` for the register `ins` writes, if any.
+fn track(known: &mut Constants, ins: &Instruction) {
+    let reg = match ins.operands[0] {
+        Operand::Register(reg) if ins.op.writes_register() => reg as usize,
+        _ => return
+    };
+    known[reg] = match (ins.op, ins.operands[1]) {
+        (Opcode::Set, Operand::Literal(value)) => Some(value),
+        (Opcode::Set, Operand::Register(src)) => known[src as usize],
+        _ => None
+    };
+}
+
+/// Disassembles a whole program image from address 0 and returns the listing.
+pub fn disassemble(memory: &[u16]) -> String {
+    analyze(memory, &[0]).listing(memory)
+}
+
+/// Which operand of a control-flow instruction holds its target.
+pub fn branch_target_index(op: Opcode) -> Option<usize> {
+    match op {
+        Opcode::Jmp | Opcode::Call => Some(0),
+        Opcode::Jt | Opcode::Jf => Some(1),
+        _ => None
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (' '..='~').contains(&c) => out.push(c),
+            c => write!(out, "\\x{:02x}", c as u32).unwrap()
+        }
+    }
+    out
+}
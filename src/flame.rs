@@ -0,0 +1,115 @@
+//! Instruction counts per unique call path, written in the folded-stacks
+//! format flame graph tools read: one `fn_0;fn_1531;fn_6027 1234` line per
+//! stack.
+
+use std::collections::HashMap;
+use crate::cpu::{CPU, Observer, StepResult};
+
+struct Node {
+    function: u16,
+    parent: Option<usize>,
+    children: HashMap<u16, usize>,
+    samples: u64,
+}
+
+/// Attach with [`CPU::run_with`] and write out [`FoldedStacks::folded`].
+/// Stacks are kept as a trie that follows the machine's shadow call stack.
+pub struct FoldedStacks {
+    nodes: Vec<Node>,
+    /// Trie node for the root and each frame of the call stack.
+    path: Vec<usize>,
+    interval: u64,
+    steps: u64,
+    merge_recursion: bool,
+    executing: bool,
+}
+
+impl Default for FoldedStacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FoldedStacks {
+    /// Counts every instruction.
+    pub fn new() -> FoldedStacks {
+        FoldedStacks::with_interval(1)
+    }
+
+    /// Takes one sample every `interval` instructions.
+    pub fn with_interval(interval: u64) -> FoldedStacks {
+        FoldedStacks { nodes: Vec::new(), path: Vec::new(), interval: interval.max(1), steps: 0, merge_recursion: false, executing: false }
+    }
+
+    /// Folds a function calling itself into a single frame, which keeps deeply
+    /// recursive stacks readable.
+    pub fn set_merge_recursion(&mut self, merge: bool) {
+        self.merge_recursion = merge;
+    }
+
+    fn child(&mut self, parent: Option<usize>, function: u16) -> usize {
+        if let Some(&index) = parent.and_then(|parent| self.nodes[parent].children.get(&function)) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.nodes.push(Node { function, parent, children: HashMap::new(), samples: 0 });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.insert(function, index);
+        }
+        index
+    }
+
+    /// One line per stack that was sampled, sorted.
+    pub fn folded(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate().filter(|(_, node)| node.samples > 0) {
+            let mut frames = Vec::new();
+            let mut cur = Some(index);
+            while let Some(i) = cur {
+                frames.push(format!("fn_{}", self.nodes[i].function));
+                cur = self.nodes[i].parent;
+            }
+            frames.reverse();
+            lines.push(format!("{} {}", frames.join(";"), node.samples));
+        }
+        lines.sort();
+        let mut out = lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Observer for FoldedStacks {
+    fn before_step(&mut self, cpu: &CPU) {
+        if self.path.is_empty() {
+            let root = self.child(None, cpu.pc() as u16);
+            self.path.push(root);
+        }
+        self.executing = !cpu.is_halted();
+    }
+
+    fn after_step(&mut self, cpu: &CPU, result: StepResult) {
+        if !self.executing || matches!(result, StepResult::NeedsInput | StepResult::Fault(_)) {
+            return;
+        }
+        // Charge the instruction to the stack it ran in, then follow any CALL or RET.
+        if self.steps % self.interval == 0 {
+            let current = *self.path.last().expect("root node");
+            self.nodes[current].samples += 1;
+        }
+        self.steps += 1;
+        let depth = cpu.call_stack().len() + 1;
+        self.path.truncate(depth);
+        while self.path.len() < depth {
+            let function = cpu.call_stack()[self.path.len() - 1].function;
+            let top = *self.path.last().expect("root node");
+            let node = match self.merge_recursion && self.nodes[top].function == function {
+                true => top,
+                false => self.child(Some(top), function)
+            };
+            self.path.push(node);
+        }
+    }
+}
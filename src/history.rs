@@ -0,0 +1,87 @@
+//! Undo records kept while the machine runs, for stepping backwards.
+
+use std::collections::VecDeque;
+use std::mem::size_of;
+use crate::cpu::Frame;
+
+/// Default memory budget for recorded history: 64 MiB.
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+/// How an instruction changed the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackChange {
+    /// The stack was untouched.
+    None,
+    /// A value was pushed; undo pops it.
+    Pushed,
+    /// This value was popped; undo pushes it back.
+    Popped(u16),
+}
+
+/// Everything one executed instruction changed, holding the old values so the
+/// change can be reverted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoRecord {
+    /// Address of the instruction.
+    pub pc: u16,
+    /// Register written and its previous value.
+    pub register: Option<(u8, u16)>,
+    /// Memory word written and its previous value.
+    pub memory: Option<(u16, u16)>,
+    /// What happened to the stack.
+    pub stack: StackChange,
+    /// Character taken from the front of the input queue.
+    pub input: Option<u16>,
+    /// Shadow call stack frame popped by `RET`.
+    pub frame: Option<Frame>,
+}
+
+/// A bounded log of undo records. When the memory budget is exhausted the
+/// oldest records are discarded.
+pub struct History {
+    records: VecDeque<UndoRecord>,
+    capacity: usize,
+}
+
+impl History {
+    /// Keeps as many records as fit in `bytes`.
+    pub fn with_budget(bytes: usize) -> History {
+        History { records: VecDeque::new(), capacity: bytes / size_of::<UndoRecord>() }
+    }
+
+    /// Maximum number of records kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of records held.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether no records are held.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Drops every record.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    /// Records an executed instruction, dropping the oldest if full.
+    pub fn push(&mut self, record: UndoRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Takes the most recent record.
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+}
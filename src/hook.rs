@@ -0,0 +1,126 @@
+//! Native hooks: Rust closures that stand in for guest subroutines. A `CALL`
+//! to a hooked address runs the closure and continues after the `CALL`, as if
+//! the subroutine had executed and returned.
+
+use std::collections::BTreeMap;
+use crate::cpu::MEMORY_SIZE;
+
+/// The machine state a hook may read and change.
+pub struct HookContext<'a> {
+    /// Registers r0-r7.
+    pub registers: &'a mut [u16; 8],
+    /// All of memory.
+    pub memory: &'a mut [u16],
+    /// The stack as the subroutine would see it, without a return address.
+    pub stack: &'a mut Vec<u16>,
+}
+
+/// Returns false to decline the call, which then runs the guest code.
+pub type Hook = Box<dyn FnMut(&mut HookContext) -> bool>;
+
+/// The hooks installed on a machine, keyed by the address they replace.
+pub struct Hooks {
+    map: BTreeMap<u16, (String, Hook)>,
+    enabled: bool,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hooks {
+    /// An empty, enabled table.
+    pub fn new() -> Hooks {
+        Hooks { map: BTreeMap::new(), enabled: true }
+    }
+
+    /// Replaces the subroutine at `addr`. `name` is only for listings.
+    pub fn insert(&mut self, addr: u16, name: &str, hook: Hook) {
+        self.map.insert(addr, (name.to_string(), hook));
+    }
+
+    /// Removes the hook at `addr`; false if there is none.
+    pub fn remove(&mut self, addr: u16) -> bool {
+        self.map.remove(&addr).is_some()
+    }
+
+    /// While disabled, calls to hooked addresses are interpreted as usual.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Whether hooks run at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether no hooks are installed.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Hooked addresses and their names, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.map.iter().map(|(&addr, (name, _))| (addr, name.as_str()))
+    }
+
+    /// The enabled hook for `addr`, if any.
+    pub fn get_mut(&mut self, addr: u16) -> Option<&mut Hook> {
+        match self.enabled {
+            true => self.map.get_mut(&addr).map(|(_, hook)| hook),
+            false => None
+        }
+    }
+}
+
+/// Names accepted by [`builtin`].
+pub const BUILTINS: &[&str] = &["teleporter"];
+
+/// A hook shipped with the VM, looked up by name.
+pub fn builtin(name: &str) -> Option<Hook> {
+    match name {
+        "teleporter" => Some(Box::new(|ctx: &mut HookContext| {
+            // Registers set above 32767 by hand are left to the guest code,
+            // which treats them differently from their 15-bit remainders.
+            if ctx.registers[1] as usize >= MEMORY_SIZE || ctx.registers[7] as usize >= MEMORY_SIZE {
+                return false;
+            }
+            let result = teleporter(ctx.registers[0], ctx.registers[1], ctx.registers[7]);
+            // The routine's last step is always `ADD r0 r1 1`.
+            ctx.registers[0] = result;
+            ctx.registers[1] = ((result as usize + MEMORY_SIZE - 1) % MEMORY_SIZE) as u16;
+            true
+        })),
+        _ => None
+    }
+}
+
+/// The teleporter confirmation routine: an Ackermann function whose
+/// `f(m, 0)` case recurses on `f(m - 1, r7)`, all modulo 32768.
+///
+/// ```text
+/// f(0, n) = n + 1
+/// f(m, 0) = f(m - 1, r7)
+/// f(m, n) = f(m - 1, f(m, n - 1))
+/// ```
+///
+/// Computed a row of `f(m, _)` at a time, each from the row before; the
+/// last row only as far as `n`. `n` and `r7` are taken modulo 32768.
+pub fn teleporter(m: u16, n: u16, r7: u16) -> u16 {
+    let (n, r7) = (n as usize % MEMORY_SIZE, r7 as usize % MEMORY_SIZE);
+    let mut row: Vec<u16> = (1..=MEMORY_SIZE).map(|n| (n % MEMORY_SIZE) as u16).collect();
+    let mut next = vec![0; MEMORY_SIZE];
+    for i in 0..m {
+        let len = if i + 1 == m { n + 1 } else { MEMORY_SIZE };
+        let mut value = row[r7];
+        next[0] = value;
+        for slot in &mut next[1..len] {
+            value = row[value as usize];
+            *slot = value;
+        }
+        std::mem::swap(&mut row, &mut next);
+    }
+    row[n]
+}
@@ -0,0 +1,224 @@
+//! The instruction set and the decoder every other module uses.
+
+use std::fmt;
+
+/// The 22 operations of the Synacor architecture, numbered as in the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    /// 0: stop execution.
+    Halt,
+    /// 1: set register a to b.
+    Set,
+    /// 2: push a onto the stack.
+    Push,
+    /// 3: pop the stack into a.
+    Pop,
+    /// 4: set a to 1 if b equals c, else 0.
+    Eq,
+    /// 5: set a to 1 if b is greater than c, else 0.
+    Gt,
+    /// 6: jump to a.
+    Jmp,
+    /// 7: jump to b if a is non-zero.
+    Jt,
+    /// 8: jump to b if a is zero.
+    Jf,
+    /// 9: a = b + c, modulo 32768.
+    Add,
+    /// 10: a = b * c, modulo 32768.
+    Mult,
+    /// 11: a = b % c.
+    Mod,
+    /// 12: a = b & c.
+    And,
+    /// 13: a = b | c.
+    Or,
+    /// 14: a = the 15-bit inverse of b.
+    Not,
+    /// 15: read memory at b into a.
+    Rmem,
+    /// 16: write b to memory at a.
+    Wmem,
+    /// 17: push the next address and jump to a.
+    Call,
+    /// 18: pop an address and jump to it; halt if the stack is empty.
+    Ret,
+    /// 19: print the character a.
+    Out,
+    /// 20: read a character into a.
+    In,
+    /// 21: do nothing.
+    Noop,
+}
+
+impl Opcode {
+    /// Every opcode, indexed by its number.
+    pub const ALL: [Opcode; 22] = [
+        Opcode::Halt, Opcode::Set, Opcode::Push, Opcode::Pop, Opcode::Eq, Opcode::Gt,
+        Opcode::Jmp, Opcode::Jt, Opcode::Jf, Opcode::Add, Opcode::Mult, Opcode::Mod,
+        Opcode::And, Opcode::Or, Opcode::Not, Opcode::Rmem, Opcode::Wmem, Opcode::Call,
+        Opcode::Ret, Opcode::Out, Opcode::In, Opcode::Noop,
+    ];
+
+    /// The opcode numbered `word`, if any.
+    pub fn from_u16(word: u16) -> Option<Opcode> {
+        Opcode::ALL.get(word as usize).copied()
+    }
+
+    /// Upper-case name used in listings.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Halt => "HALT",
+            Opcode::Set => "SET",
+            Opcode::Push => "PUSH",
+            Opcode::Pop => "POP",
+            Opcode::Eq => "EQ",
+            Opcode::Gt => "GT",
+            Opcode::Jmp => "JMP",
+            Opcode::Jt => "JT",
+            Opcode::Jf => "JF",
+            Opcode::Add => "ADD",
+            Opcode::Mult => "MULT",
+            Opcode::Mod => "MOD",
+            Opcode::And => "AND",
+            Opcode::Or => "OR",
+            Opcode::Not => "NOT",
+            Opcode::Rmem => "RMEM",
+            Opcode::Wmem => "WMEM",
+            Opcode::Call => "CALL",
+            Opcode::Ret => "RET",
+            Opcode::Out => "OUT",
+            Opcode::In => "IN",
+            Opcode::Noop => "NOOP",
+        }
+    }
+
+    /// Number of operand words following the opcode.
+    pub fn arity(self) -> usize {
+        match self {
+            Opcode::Halt | Opcode::Ret | Opcode::Noop => 0,
+            Opcode::Push | Opcode::Pop | Opcode::Jmp | Opcode::Call | Opcode::Out | Opcode::In => 1,
+            Opcode::Set | Opcode::Jt | Opcode::Jf | Opcode::Not | Opcode::Rmem | Opcode::Wmem => 2,
+            Opcode::Eq | Opcode::Gt | Opcode::Add | Opcode::Mult | Opcode::Mod | Opcode::And | Opcode::Or => 3,
+        }
+    }
+
+    /// Whether the first operand names the register the result is written to.
+    pub fn writes_register(self) -> bool {
+        matches!(self,
+            Opcode::Set | Opcode::Pop | Opcode::Eq | Opcode::Gt | Opcode::Add | Opcode::Mult |
+            Opcode::Mod | Opcode::And | Opcode::Or | Opcode::Not | Opcode::Rmem | Opcode::In)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+/// A decoded operand: words 0..=32767 are literals, 32768..=32775 name registers 0..=7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// A value used as is.
+    Literal(u16),
+    /// One of the registers 0..=7.
+    Register(u8),
+}
+
+impl Operand {
+    /// Decodes an operand word; `None` above 32775.
+    pub fn from_word(word: u16) -> Option<Operand> {
+        match word {
+            0..=32767 => Some(Operand::Literal(word)),
+            32768..=32775 => Some(Operand::Register((word - 32768) as u8)),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Literal(value) => write!(f, "{}", value),
+            Operand::Register(reg) => write!(f, "R{}", reg),
+        }
+    }
+}
+
+/// One decoded instruction. Only the first `len - 1` entries of `operands` are meaningful.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    /// The operation.
+    pub op: Opcode,
+    /// Operand words, unused ones set to literal 0.
+    pub operands: [Operand; 3],
+    /// Words the instruction takes, opcode included.
+    pub len: usize,
+}
+
+impl Instruction {
+    /// The operands the opcode uses.
+    pub fn args(&self) -> &[Operand] {
+        &self.operands[..self.len - 1]
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        for arg in self.args() {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why the words at an address are not an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The word at `addr` is not an opcode.
+    InvalidOpcode {
+        /// Address of the instruction.
+        addr: u16,
+        /// The word found there.
+        opcode: u16,
+    },
+    /// An operand is neither a literal nor a register.
+    InvalidOperand {
+        /// Address of the instruction.
+        addr: u16,
+        /// The operand word.
+        value: u16,
+    },
+    /// The instruction's operands run past the end of memory.
+    Truncated {
+        /// Address of the instruction.
+        addr: u16,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidOpcode { addr, opcode } => write!(f, "invalid operation {} at {}", opcode, addr),
+            DecodeError::InvalidOperand { addr, value } => write!(f, "invalid operand {} at {}", value, addr),
+            DecodeError::Truncated { addr } => write!(f, "instruction at {} runs past the end of memory", addr),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the instruction starting at `addr`.
+pub fn decode(memory: &[u16], addr: usize) -> Result<Instruction, DecodeError> {
+    let at = addr as u16;
+    let word = *memory.get(addr).ok_or(DecodeError::Truncated { addr: at })?;
+    let op = Opcode::from_u16(word).ok_or(DecodeError::InvalidOpcode { addr: at, opcode: word })?;
+    let mut operands = [Operand::Literal(0); 3];
+    for (i, operand) in operands.iter_mut().enumerate().take(op.arity()) {
+        let value = *memory.get(addr + 1 + i).ok_or(DecodeError::Truncated { addr: at })?;
+        *operand = Operand::from_word(value).ok_or(DecodeError::InvalidOperand { addr: at, value })?;
+    }
+    Ok(Instruction { op, operands, len: 1 + op.arity() })
+}
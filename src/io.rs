@@ -0,0 +1,250 @@
+//! Where the `IN` instruction gets its characters and where `OUT` sends them.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Supplies input to the machine one line at a time.
+pub trait InputSource {
+    /// Returns the next line including its trailing newline, or `None` once exhausted.
+    fn read_line(&mut self) -> Option<String>;
+
+    /// Whether the line most recently returned by `read_line` should be copied
+    /// to the output, so that transcripts of replayed input stay readable.
+    fn echo(&self) -> bool {
+        false
+    }
+}
+
+/// Receives every character the program prints.
+pub trait OutputSink {
+    /// Receives one character printed by `OUT`.
+    fn write_char(&mut self, c: char);
+
+    /// Called before the machine blocks on input.
+    fn flush(&mut self) {}
+}
+
+/// Reads lines from the process's standard input.
+#[derive(Default)]
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn read_line(&mut self) -> Option<String> {
+        let mut buffer = String::new();
+        match io::stdin().read_line(&mut buffer) {
+            Ok(n) if n > 0 => Some(buffer),
+            _ => None
+        }
+    }
+}
+
+/// Writes to the process's standard output.
+#[derive(Default)]
+pub struct StdoutOutput;
+
+impl OutputSink for StdoutOutput {
+    fn write_char(&mut self, c: char) {
+        print!("{}", c);
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Serves lines from an in-memory string. Always runs dry eventually.
+#[derive(Default)]
+pub struct BufferInput {
+    lines: VecDeque<String>
+}
+
+impl BufferInput {
+    /// Serves the lines of `text`.
+    pub fn new(text: &str) -> BufferInput {
+        let mut input = BufferInput::default();
+        input.push(text);
+        input
+    }
+
+    /// Appends more text; a missing final newline is added.
+    pub fn push(&mut self, text: &str) {
+        for line in text.lines() {
+            self.lines.push_back(format!("{}\n", line));
+        }
+    }
+}
+
+impl InputSource for BufferInput {
+    fn read_line(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+}
+
+/// Collects output in memory. Clones share the same buffer, so keep one
+/// handle and give the other to the machine.
+#[derive(Clone, Default)]
+pub struct BufferOutput {
+    buffer: Rc<RefCell<String>>
+}
+
+impl BufferOutput {
+    /// An empty buffer.
+    pub fn new() -> BufferOutput {
+        BufferOutput::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        self.buffer.borrow().clone()
+    }
+
+    /// Returns everything written so far and clears the buffer.
+    pub fn take(&self) -> String {
+        std::mem::take(&mut *self.buffer.borrow_mut())
+    }
+}
+
+impl OutputSink for BufferOutput {
+    fn write_char(&mut self, c: char) {
+        self.buffer.borrow_mut().push(c);
+    }
+}
+
+/// Replays one or more scripts, then optionally hands over to another source
+/// such as [`StdinInput`].
+#[derive(Default)]
+pub struct ScriptInput {
+    script: BufferInput,
+    fallback: Option<Box<dyn InputSource>>,
+    echo: bool,
+    replaying: bool
+}
+
+impl ScriptInput {
+    /// A source with no scripts and no fallback.
+    pub fn new() -> ScriptInput {
+        ScriptInput::default()
+    }
+
+    /// Appends a script given as text, one command per line.
+    pub fn push_script(&mut self, text: &str) {
+        self.script.push(text);
+    }
+
+    /// Appends the contents of a script file.
+    pub fn read_script<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        self.push_script(&text);
+        Ok(())
+    }
+
+    /// Source to read from once every script line has been replayed.
+    pub fn set_fallback(&mut self, fallback: Box<dyn InputSource>) {
+        self.fallback = Some(fallback);
+    }
+
+    /// Echo replayed lines (but not fallback input) to the output.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+}
+
+impl InputSource for ScriptInput {
+    fn read_line(&mut self) -> Option<String> {
+        if let Some(line) = self.script.read_line() {
+            self.replaying = true;
+            return Some(line);
+        }
+        self.replaying = false;
+        self.fallback.as_mut().and_then(|fallback| fallback.read_line())
+    }
+
+    fn echo(&self) -> bool {
+        if self.replaying {
+            self.echo
+        } else {
+            self.fallback.as_ref().is_some_and(|fallback| fallback.echo())
+        }
+    }
+}
+
+/// Reads lines from a file as they are requested.
+pub struct FileInput {
+    reader: BufReader<File>
+}
+
+impl FileInput {
+    /// Opens the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileInput> {
+        Ok(FileInput { reader: BufReader::new(File::open(path)?) })
+    }
+}
+
+impl InputSource for FileInput {
+    fn read_line(&mut self) -> Option<String> {
+        let mut buffer = String::new();
+        match self.reader.read_line(&mut buffer) {
+            Ok(n) if n > 0 => {
+                if !buffer.ends_with('\n') {
+                    buffer.push('\n');
+                }
+                Some(buffer)
+            },
+            _ => None
+        }
+    }
+}
+
+/// Writes output to a file.
+pub struct FileOutput {
+    writer: BufWriter<File>
+}
+
+impl FileOutput {
+    /// Creates or truncates the file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<FileOutput> {
+        Ok(FileOutput { writer: BufWriter::new(File::create(path)?) })
+    }
+}
+
+impl OutputSink for FileOutput {
+    fn write_char(&mut self, c: char) {
+        let mut bytes = [0; 4];
+        let _ = self.writer.write_all(c.encode_utf8(&mut bytes).as_bytes());
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Copies output to several sinks.
+#[derive(Default)]
+pub struct TeeOutput {
+    sinks: Vec<Box<dyn OutputSink>>
+}
+
+impl TeeOutput {
+    /// Writes to each of `sinks`, in order.
+    pub fn new(sinks: Vec<Box<dyn OutputSink>>) -> TeeOutput {
+        TeeOutput { sinks }
+    }
+}
+
+impl OutputSink for TeeOutput {
+    fn write_char(&mut self, c: char) {
+        for sink in &mut self.sinks {
+            sink.write_char(c);
+        }
+    }
+
+    fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            sink.flush();
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! A virtual machine and tooling for the Synacor Challenge architecture.
+//!
+//! The [`CPU`] executes one instruction at a time through [`CPU::step`],
+//! [`instruction::decode`] is the single description of the instruction set,
+//! [`asm`] turns source text into words, [`disasm`] turns them back and
+//! [`cfg`](mod@cfg) groups them into blocks and functions for [`decompile`],
+//! [`loader`] reads program images, [`io`] connects `IN`/`OUT` to the outside
+//! world and [`SaveState`] captures the whole machine.
+//!
+//! ```no_run
+//! use synacor::CPU;
+//! use synacor::io::{BufferInput, BufferOutput};
+//!
+//! let output = BufferOutput::new();
+//! let input = BufferInput::new("take tablet\nuse tablet\n");
+//! let mut cpu = CPU::with_io(Box::new(input), Box::new(output.clone()));
+//! cpu.read_binary("challenge.bin").unwrap();
+//! cpu.run();
+//! println!("{}", output.contents());
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod asm;
+pub mod breakpoint;
+pub mod cfg;
+pub mod coverage;
+pub mod cpu;
+pub mod debugger;
+pub mod decompile;
+pub mod diff;
+pub mod disasm;
+pub mod flame;
+pub mod history;
+pub mod hook;
+pub mod instruction;
+pub mod io;
+pub mod loader;
+pub mod patch;
+pub mod profile;
+pub mod snapshot;
+pub mod solve;
+pub mod trace;
+pub mod watch;
+
+pub use cpu::{CPU, Fault, Frame, Observer, StepResult};
+pub use debugger::Debugger;
+pub use instruction::{decode, DecodeError, Instruction, Opcode, Operand};
+pub use snapshot::SaveState;
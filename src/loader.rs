@@ -0,0 +1,29 @@
+//! Program images on disk: little-endian 16-bit words.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads a program image: a sequence of little-endian 16-bit words.
+pub fn read_binary<P: AsRef<Path>>(filename: P) -> io::Result<Vec<u16>> {
+    let buffer = fs::read(filename)?;
+    Ok(parse_binary(&buffer))
+}
+
+/// Converts raw bytes into words. A trailing odd byte is ignored.
+pub fn parse_binary(buffer: &[u8]) -> Vec<u16> {
+    buffer
+        .chunks_exact(2)
+        .map(|pair| (u16::from(pair[1]) << 8) | u16::from(pair[0]))
+        .collect()
+}
+
+/// Converts words into the little-endian bytes of a program image.
+pub fn to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Writes a program image that `read_binary` can load.
+pub fn write_binary<P: AsRef<Path>>(filename: P, words: &[u16]) -> io::Result<()> {
+    fs::write(filename, to_bytes(words))
+}
@@ -1,82 +1,605 @@
-mod cpu;
-use std::fs::File;
-use std::io::Read;
-use std::collections::HashMap;
-
-
-fn main() {
-    let mut cpu = cpu::CPU::new();
-    //disassemble(&String::from("/Users/adriansjohag/Documents/Synacor/challenge.bin"));
-    cpu.read_binary(&String::from("/Users/adriansjohag/Documents/Synacor/challenge.bin"));
-    cpu.run();
-}
-#[allow(dead_code)]
-fn disassemble(filename: &String) {
-    let mut binary: Vec<u16> = Vec::new();
-    let mut f =  File::open(&filename).expect("No file found");
-    let metadata = f.metadata().expect("Unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("Buffer overflow");
-    
-    for x in (1..metadata.len() as usize).step_by(2) {
-        let low: u8 = buffer[x - 1];
-        let high: u8 = buffer[x];
-        let byte: u16 = u16::from(high) << 8 | u16::from(low & 0xff);
-        binary.push(byte);
-    }
-    dump_binary(binary);
-}
-
-fn dump_binary(bin: Vec<u16>) {
-    let opcode_map = HashMap::from([
-        (0, String::from("HALT")), // 0
-        (21, String::from("NOOP")), // 0,
-        (2, String::from("PUSH")), // 1
-        (3, String::from("POP")), // 1
-        (6, String::from("JMP")), // 1
-        (20, String::from("IN")), // 1
-        (19, String::from("OUT")), // 1
-        (17, String::from("CALL")), // 1
-        (7, String::from("JT")), // 2
-        (8, String::from("JF")), // 2
-        (15, String::from("RMEM")), // 2
-        (14, String::from("NOT")), // 2
-        (16, String::from("WMEM")), // 2
-        (1, String::from("SET")), // 2
-        (9, String::from("ADD")), // 3
-        (5, String::from("GT")), // 3
-        (4, String::from("EQ")), // 3
-        (10, String::from("MULT")), // 3
-        (11, String::from("MOD")), // 3
-        (12, String::from("AND")), // 3
-        (13, String::from("OR")), // 3
-        (18, String::from("RET")) // 1 from stack
-    ]);
-
-    let reg_map  = HashMap::from([
-        (32768, String::from("R0")),
-        (32769, String::from("R1")),
-        (32770, String::from("R2")),
-        (32771, String::from("R3")),
-        (32772, String::from("R4")),
-        (32773, String::from("R5")),
-        (32774, String::from("R6")),
-        (32775, String::from("R7"))
-    ]);
-    let mut dumb_data = "".to_owned();
-    let mut index = 0;
-    for byte in bin {
-        dumb_data.push_str(&index.to_string());
-        dumb_data.push_str(" ");
-        if reg_map.contains_key(&byte) {
-            dumb_data.push_str(&reg_map[&byte]);
-        } else if opcode_map.contains_key(&byte) {
-            dumb_data.push_str(&opcode_map[&byte]);
-        } else {
-            dumb_data.push_str(&byte.to_string());
-        }
-        dumb_data.push_str("\n");
-        index += 1;
-    }
-    std::fs::write("dump.txt", dumb_data).expect("Failed to dump file");
-}
\ No newline at end of file
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use synacor::asm::{self, AsmError};
+use synacor::debugger::Exit;
+use synacor::snapshot::SnapshotError;
+use synacor::solve::{solve_teleporter, TeleporterCheck};
+use synacor::io::{FileOutput, OutputSink, ScriptInput, StdinInput, StdoutOutput, TeeOutput};
+use synacor::coverage::{Coverage, CoverageError};
+use synacor::flame::FoldedStacks;
+use synacor::hook;
+use synacor::patch::{self, PatchError};
+use synacor::profile::Profiler;
+use synacor::trace::{Filter, Format, Tracer};
+use synacor::{CPU, Debugger, Observer, Opcode, SaveState, StepResult};
+use synacor::breakpoint::parse_number;
+use synacor::cfg::Program;
+use synacor::diff::{self, SnapshotDiff};
+use synacor::{decompile, disasm, history, loader};
+
+const USAGE: &str = "\
+usage: synacor-rust <command> [options]
+
+commands:
+    run <bin>       run a program with stdin/stdout attached
+    trace <bin>     run a program recording every instruction; --trace defaults
+                    to trace.jsonl, or trace.bin with --trace-format binary
+    debug <bin>     run a program under the interactive debugger
+                    (<bin> may be left out when --load is given)
+    disasm <bin>    disassemble a program image, following control flow from address 0
+    asm <src>       assemble a source file into a program image
+    cfg <bin>       print the call graph as Graphviz DOT
+    decompile <bin> print C-like pseudo-code for every function
+    coverage <bin> <cov>...  report which functions and blocks the coverage files reach
+    diff <save> <save>...    show what changed between consecutive save files
+    solve teleporter <bin>   find the r7 value the teleporter check accepts
+
+options:
+    -i, --input <file>     replay the lines of <file> as program input; repeatable (run, debug)
+        --interactive      keep reading stdin once the input scripts are used up
+        --echo             copy replayed input lines into the program output
+        --load <file>      resume from a save file (run, debug)
+        --patch <file>     apply a patch file once the program is loaded; repeatable (run, debug)
+        --hook <addr>=<name>  run the built-in native hook <name> instead of the subroutine
+                           at <addr>; repeatable (run, debug). Hooks: teleporter
+        --history <MiB>    memory budget for reverse execution, 0 to disable (debug, default 64)
+        --save-dir <dir>   directory of named save slots, default saves/ (debug)
+        --stop             open the debugger console before the first instruction (debug)
+        --trace <file>     record every executed instruction to <file> (run, trace)
+        --trace-format <f> jsonl (default) or binary
+        --trace-range <a>..<b>  only trace instructions at addresses a..b; repeatable
+        --trace-op <ops>   only trace these opcodes, e.g. call,ret; repeatable
+        --trace-steps <a>..<b>  only trace steps a..b, counted from 0; either end may be left out
+        --profile          count executions per address and function; report on stderr at exit (run)
+        --profile-top <n>  rows per report table, default 20
+        --flame <file>     write instruction counts per call stack in folded-stacks format (run)
+        --flame-interval <n>  sample every <n>th instruction instead of counting all of them
+        --flame-merge-recursion  fold a function calling itself into one frame
+        --coverage <file>  add the executed addresses to <file>, creating it if needed (run)
+        --listing <file>   coverage: also write a disassembly marked + executed, - not executed
+        --times <n>        diff: only list memory words that changed exactly <n> times
+        --threads <n>      solve: worker threads, default one per CPU
+    -o, --output <file>    run, debug: also write program output to <file>
+                           disasm: write the listing to <file> instead of stdout
+                           asm: the image to write, default <src> with a .bin extension
+                           cfg: a directory to fill with callgraph.dot and fn_<addr>.dot
+                           decompile: write the pseudo-code to <file> instead of stdout
+                           solve: write a patch file that skips the check
+        --function <addr>  cfg: print the control-flow graph of one function instead
+                           decompile: only decompile the function at <addr>
+        --entry <addr>     disasm, cfg, decompile, coverage: also follow control flow from <addr>; repeatable
+    -h, --help             show this message
+
+debugger:
+    At the game prompt a line of just ! opens the (debug) console, !<command>
+    runs a single console command and !!text sends !text to the program. Type
+    help at the console for its commands.
+
+exit codes:
+    0  program halted or the user quit
+    1  the program faulted
+    2  bad command line
+    3  a file could not be read or written, or a save file is invalid
+    4  input ran out while the program was waiting for more
+    5  the source or a patch file could not be assembled or applied
+    6  no function starts at the --function address";
+
+/// Rows in each table of the `--profile` report unless `--profile-top` says otherwise.
+const DEFAULT_PROFILE_TOP: usize = 20;
+
+const EXIT_FAULT: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_IO: u8 = 3;
+const EXIT_INPUT_CLOSED: u8 = 4;
+const EXIT_ASM: u8 = 5;
+const EXIT_NO_FUNCTION: u8 = 6;
+
+enum CliError {
+    Usage(String),
+    Io(String, io::Error),
+    Save(String, SnapshotError),
+    Coverage(String, CoverageError),
+    Asm(String, AsmError),
+    Patch(String, PatchError),
+    NoFunction(String, u16)
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Io(_, _) | CliError::Save(_, _) | CliError::Coverage(_, _) => EXIT_IO,
+            CliError::Asm(_, _) | CliError::Patch(_, _) => EXIT_ASM,
+            CliError::NoFunction(_, _) => EXIT_NO_FUNCTION
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            CliError::Io(path, err) => write!(f, "{}: {}", path, err),
+            CliError::Save(path, err) => write!(f, "{}: {}", path, err),
+            CliError::Coverage(path, err) => write!(f, "{}: {}", path, err),
+            CliError::Asm(path, err) => write!(f, "{}: {}", path, err),
+            CliError::Patch(path, err) => write!(f, "{}: {}", path, err),
+            CliError::NoFunction(path, addr) => write!(f, "{}: no function starts at {}", path, addr)
+        }
+    }
+}
+
+/// Command-line arguments split into positionals and `--name value` options.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>
+}
+
+impl Args {
+    /// `valued` lists options that take a value, `flags` those that do not.
+    /// Each entry is `(long, short)`; options are stored under their long name.
+    fn parse(args: &[String], valued: &[(&str, &str)], flags: &[(&str, &str)]) -> Result<Args, CliError> {
+        let mut parsed = Args { positional: Vec::new(), options: Vec::new() };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                parsed.positional.push(arg.clone());
+                continue;
+            }
+            let matches = |&&(long, short): &&(&str, &str)| arg == long || (!short.is_empty() && arg == short);
+            if let Some(&(long, _)) = valued.iter().find(matches) {
+                let value = iter.next().ok_or_else(|| CliError::Usage(format!("{} needs a value", arg)))?;
+                parsed.options.push((long.to_string(), Some(value.clone())));
+            } else if let Some(&(long, _)) = flags.iter().find(matches) {
+                parsed.options.push((long.to_string(), None));
+            } else {
+                return Err(CliError::Usage(format!("unknown option {}", arg)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).pop()
+    }
+
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.options.iter()
+            .filter(|(option, _)| option == name)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    /// The single positional argument a subcommand operates on.
+    fn file(&self) -> Result<&str, CliError> {
+        match self.positional.as_slice() {
+            [file] => Ok(file),
+            [] => Err(CliError::Usage(String::from("missing file argument"))),
+            _ => Err(CliError::Usage(String::from("too many arguments")))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run_cli(&args) {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run_cli(args: &[String]) -> Result<u8, CliError> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(CliError::Usage(String::from("missing command")))
+    };
+    match command {
+        "run" => cmd_run(rest, false),
+        "trace" => cmd_trace(rest),
+        "debug" => cmd_run(rest, true),
+        "disasm" => cmd_disasm(rest),
+        "asm" => cmd_asm(rest),
+        "cfg" => cmd_cfg(rest),
+        "decompile" => cmd_decompile(rest),
+        "coverage" => cmd_coverage(rest),
+        "diff" => cmd_diff(rest),
+        "solve" => cmd_solve(rest),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(0)
+        },
+        _ => Err(CliError::Usage(format!("unknown command {}", command)))
+    }
+}
+
+fn cmd_run(args: &[String], debug: bool) -> Result<u8, CliError> {
+    let args = Args::parse(
+        args,
+        &[
+            ("--input", "-i"), ("--output", "-o"), ("--load", ""), ("--save-dir", ""), ("--history", ""), ("--patch", ""), ("--hook", ""),
+            ("--trace", ""), ("--trace-format", ""), ("--trace-range", ""), ("--trace-op", ""), ("--trace-steps", ""),
+            ("--profile-top", ""), ("--flame", ""), ("--flame-interval", ""), ("--coverage", "")
+        ],
+        &[("--interactive", ""), ("--echo", ""), ("--stop", ""), ("--profile", ""), ("--flame-merge-recursion", "")]
+    )?;
+    if debug && args.value("--trace").is_some() {
+        return Err(CliError::Usage(String::from("--trace only works with run")));
+    }
+    if debug && args.flag("--profile") {
+        return Err(CliError::Usage(String::from("--profile only works with run")));
+    }
+    if debug && args.value("--flame").is_some() {
+        return Err(CliError::Usage(String::from("--flame only works with run")));
+    }
+    if debug && args.value("--coverage").is_some() {
+        return Err(CliError::Usage(String::from("--coverage only works with run")));
+    }
+    let interval = match args.value("--flame-interval") {
+        Some(n) => n.parse::<u64>().ok().filter(|&n| n > 0)
+            .ok_or_else(|| CliError::Usage(format!("bad --flame-interval value {}", n)))?,
+        None => 1
+    };
+    let top = match args.value("--profile-top") {
+        Some(top) => top.parse::<usize>().map_err(|_| CliError::Usage(format!("bad --profile-top value {}", top)))?,
+        None => DEFAULT_PROFILE_TOP
+    };
+    let mut cpu = CPU::new();
+    match args.value("--load") {
+        Some(save) => {
+            if args.positional.len() > 1 {
+                return Err(CliError::Usage(String::from("too many arguments")));
+            }
+            let state = SaveState::load(save).map_err(|err| CliError::Save(save.to_string(), err))?;
+            cpu.restore(&state);
+        },
+        None => {
+            let filename = args.file()?;
+            cpu.read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+        }
+    }
+    for file in args.values("--patch") {
+        let source = fs::read_to_string(file).map_err(|err| CliError::Io(file.to_string(), err))?;
+        for patch in patch::parse(&source).map_err(|err| CliError::Patch(file.to_string(), err))? {
+            cpu.apply_patch(patch).map_err(|err| CliError::Patch(file.to_string(), err))?;
+        }
+    }
+    for patch in cpu.patches().iter() {
+        eprintln!("patch {}", patch);
+    }
+    for spec in args.values("--hook") {
+        let bad = || CliError::Usage(format!("bad --hook {}, expected <addr>=<name>", spec));
+        let (addr, name) = spec.split_once('=').ok_or_else(bad)?;
+        let addr = parse_number(addr).map_err(|_| bad())?;
+        let hook = hook::builtin(name)
+            .ok_or_else(|| CliError::Usage(format!("unknown hook {}, expected one of {}", name, hook::BUILTINS.join(", "))))?;
+        cpu.hooks().insert(addr, name, hook);
+        eprintln!("hook {} at {}", name, addr);
+    }
+    if let Some(out) = args.value("--output") {
+        let file = FileOutput::create(out).map_err(|err| CliError::Io(out.to_string(), err))?;
+        let sinks: Vec<Box<dyn OutputSink>> = vec![Box::new(StdoutOutput), Box::new(file)];
+        cpu.set_output(Box::new(TeeOutput::new(sinks)));
+    }
+    let scripts = args.values("--input");
+    if !scripts.is_empty() {
+        let mut input = ScriptInput::new();
+        for script in scripts {
+            input.read_script(script).map_err(|err| CliError::Io(script.to_string(), err))?;
+        }
+        if args.flag("--interactive") {
+            input.set_fallback(Box::new(StdinInput));
+        }
+        input.set_echo(args.flag("--echo"));
+        cpu.set_input(Box::new(input));
+    }
+
+    let exit = if debug {
+        let budget = match args.value("--history") {
+            Some(mib) => mib.parse::<usize>()
+                .map_err(|_| CliError::Usage(format!("bad --history value {}", mib)))? * 1024 * 1024,
+            None => history::DEFAULT_BUDGET
+        };
+        if budget > 0 {
+            cpu.enable_history(budget);
+        }
+        let mut debugger = Debugger::new();
+        if let Some(save_dir) = args.value("--save-dir") {
+            debugger.set_save_dir(save_dir);
+        }
+        if args.flag("--stop") {
+            debugger.stop();
+        }
+        debugger.run(&mut cpu)
+    } else {
+        let mut tracer = tracer(&args)?;
+        let mut profiler = args.flag("--profile").then(Profiler::new);
+        let mut flame = args.value("--flame").map(|_| {
+            let mut flame = FoldedStacks::with_interval(interval);
+            flame.set_merge_recursion(args.flag("--flame-merge-recursion"));
+            flame
+        });
+        // Read the existing file up front so a bad one is reported before the run.
+        let previous = match args.value("--coverage") {
+            Some(path) if Path::new(path).exists() => {
+                Some(Coverage::load(path).map_err(|err| CliError::Coverage(path.to_string(), err))?)
+            },
+            _ => None
+        };
+        let mut coverage = args.value("--coverage").map(|_| Coverage::new());
+        let mut observers: Vec<&mut dyn Observer> = Vec::new();
+        if let Some(tracer) = &mut tracer {
+            observers.push(tracer);
+        }
+        if let Some(profiler) = &mut profiler {
+            observers.push(profiler);
+        }
+        if let Some(flame) = &mut flame {
+            observers.push(flame);
+        }
+        if let Some(coverage) = &mut coverage {
+            observers.push(coverage);
+        }
+        let result = cpu.run_with(&mut observers);
+        if let (Some(tracer), Some(path)) = (tracer, args.value("--trace")) {
+            tracer.finish().map_err(|err| CliError::Io(path.to_string(), err))?;
+        }
+        if let Some(profiler) = profiler {
+            eprint!("{}", profiler.report(&cpu, top));
+        }
+        if let (Some(flame), Some(path)) = (flame, args.value("--flame")) {
+            fs::write(path, flame.folded()).map_err(|err| CliError::Io(path.to_string(), err))?;
+        }
+        if let (Some(mut coverage), Some(path)) = (coverage, args.value("--coverage")) {
+            if let Some(previous) = previous {
+                coverage.merge(&previous);
+            }
+            coverage.save(path).map_err(|err| CliError::Coverage(path.to_string(), err))?;
+        }
+        match result {
+            StepResult::Fault(fault) => Exit::Fault(fault),
+            StepResult::NeedsInput => Exit::InputClosed,
+            _ => Exit::Halted
+        }
+    };
+    match exit {
+        Exit::Halted | Exit::Quit => Ok(0),
+        Exit::Fault(fault) => {
+            eprintln!("{}", fault);
+            Ok(EXIT_FAULT)
+        },
+        Exit::InputClosed => {
+            eprintln!("input closed while the program was waiting for more");
+            Ok(EXIT_INPUT_CLOSED)
+        }
+    }
+}
+
+/// `run` with `--trace` given a default file if it is left out.
+fn cmd_trace(args: &[String]) -> Result<u8, CliError> {
+    let mut args = args.to_vec();
+    if !args.iter().any(|arg| arg == "--trace") {
+        let binary = args.windows(2).any(|pair| pair[0] == "--trace-format" && pair[1] == "binary");
+        args.push(String::from("--trace"));
+        args.push(String::from(if binary { "trace.bin" } else { "trace.jsonl" }));
+    }
+    cmd_run(&args, false)
+}
+
+/// The tracer asked for by `--trace` and its filter options.
+fn tracer(args: &Args) -> Result<Option<Tracer<BufWriter<File>>>, CliError> {
+    let path = match args.value("--trace") {
+        Some(path) => path,
+        None => return Ok(None)
+    };
+    let format = match args.value("--trace-format") {
+        None | Some("jsonl") => Format::Jsonl,
+        Some("binary") => Format::Binary,
+        Some(other) => return Err(CliError::Usage(format!("unknown trace format {}", other)))
+    };
+    let mut filter = Filter::default();
+    for range in args.values("--trace-range") {
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        match (parse_number(start), parse_number(end)) {
+            (Ok(start), Ok(end)) if start <= end => filter.addresses.push(start..=end),
+            _ => return Err(CliError::Usage(format!("bad --trace-range {}", range)))
+        }
+    }
+    for names in args.values("--trace-op") {
+        for name in names.split(',') {
+            let op = Opcode::ALL.iter().find(|op| op.mnemonic().eq_ignore_ascii_case(name))
+                .ok_or_else(|| CliError::Usage(format!("unknown opcode {}", name)))?;
+            filter.opcodes.push(*op);
+        }
+    }
+    if let Some(steps) = args.value("--trace-steps") {
+        let bad = || CliError::Usage(format!("bad --trace-steps {}", steps));
+        let (start, end) = steps.split_once("..").ok_or_else(bad)?;
+        let start = if start.is_empty() { 0 } else { start.parse::<u64>().map_err(|_| bad())? };
+        let end = if end.is_empty() { u64::MAX } else { end.parse::<u64>().map_err(|_| bad())? };
+        filter.steps = Some(start..=end);
+    }
+    let file = File::create(path).map_err(|err| CliError::Io(path.to_string(), err))?;
+    Ok(Some(Tracer::new(BufWriter::new(file), format, filter)))
+}
+
+fn cmd_disasm(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--output", "-o"), ("--entry", "")], &[])?;
+    let filename = args.file()?;
+    let binary = loader::read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let listing = disasm::analyze(&binary, &entry_points(&args)?).listing(&binary);
+    match args.value("--output") {
+        Some(out) => fs::write(out, listing).map_err(|err| CliError::Io(out.to_string(), err))?,
+        None => print!("{}", listing)
+    }
+    Ok(0)
+}
+
+fn cmd_cfg(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--output", "-o"), ("--entry", ""), ("--function", "")], &[])?;
+    let filename = args.file()?;
+    let binary = loader::read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let dis = disasm::analyze(&binary, &entry_points(&args)?);
+    let program = Program::build(&dis);
+    if let Some(function) = args.value("--function") {
+        let entry = parse_number(function).map_err(|_| CliError::Usage(format!("bad --function address {}", function)))?;
+        let dot = program.function_dot(entry, &dis)
+            .ok_or_else(|| CliError::NoFunction(filename.to_string(), entry))?;
+        print!("{}", dot);
+        return Ok(0);
+    }
+    match args.value("--output") {
+        Some(dir) => {
+            let write = |name: String, dot: String| {
+                let path = Path::new(dir).join(name);
+                fs::write(&path, dot).map_err(|err| CliError::Io(path.display().to_string(), err))
+            };
+            fs::create_dir_all(dir).map_err(|err| CliError::Io(dir.to_string(), err))?;
+            write(String::from("callgraph.dot"), program.call_graph_dot())?;
+            for &entry in program.functions.keys() {
+                if let Some(dot) = program.function_dot(entry, &dis) {
+                    write(format!("fn_{}.dot", entry), dot)?;
+                }
+            }
+        },
+        None => print!("{}", program.call_graph_dot())
+    }
+    Ok(0)
+}
+
+fn cmd_decompile(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--output", "-o"), ("--entry", ""), ("--function", "")], &[])?;
+    let filename = args.file()?;
+    let binary = loader::read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let dis = disasm::analyze(&binary, &entry_points(&args)?);
+    let program = Program::build(&dis);
+    let text = match args.value("--function") {
+        Some(function) => {
+            let entry = parse_number(function).map_err(|_| CliError::Usage(format!("bad --function address {}", function)))?;
+            decompile::decompile_function(&program, &dis, entry)
+                .ok_or_else(|| CliError::NoFunction(filename.to_string(), entry))?
+        },
+        None => decompile::decompile(&program, &dis)
+    };
+    match args.value("--output") {
+        Some(out) => fs::write(out, text).map_err(|err| CliError::Io(out.to_string(), err))?,
+        None => print!("{}", text)
+    }
+    Ok(0)
+}
+
+fn cmd_coverage(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--listing", ""), ("--entry", "")], &[])?;
+    let (filename, files) = match args.positional.split_first() {
+        Some((filename, files)) if !files.is_empty() => (filename, files),
+        _ => return Err(CliError::Usage(String::from("coverage needs a program and at least one coverage file")))
+    };
+    let binary = loader::read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let mut coverage = Coverage::new();
+    for file in files {
+        coverage.merge(&Coverage::load(file).map_err(|err| CliError::Coverage(file.to_string(), err))?);
+    }
+    let dis = coverage.analyze(&binary, &entry_points(&args)?);
+    print!("{}", coverage.report(&dis, &Program::build(&dis)));
+    if let Some(out) = args.value("--listing") {
+        fs::write(out, coverage.annotated_listing(&dis, &binary)).map_err(|err| CliError::Io(out.to_string(), err))?;
+    }
+    Ok(0)
+}
+
+fn cmd_diff(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--times", "")], &[])?;
+    if args.positional.len() < 2 {
+        return Err(CliError::Usage(String::from("diff needs at least two save files")));
+    }
+    let mut states = Vec::new();
+    for file in &args.positional {
+        states.push(SaveState::load(file).map_err(|err| CliError::Save(file.to_string(), err))?);
+    }
+    if let Some(times) = args.value("--times") {
+        let times = times.parse::<usize>().map_err(|_| CliError::Usage(format!("bad --times value {}", times)))?;
+        for (addr, values) in diff::changed_n_times(&states, times) {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            println!("{:>5}: {}", addr, values.join(" "));
+        }
+        return Ok(0);
+    }
+    for (i, pair) in states.windows(2).enumerate() {
+        if states.len() > 2 {
+            println!("{}{} -> {}", if i > 0 { "\n" } else { "" }, args.positional[i], args.positional[i + 1]);
+        }
+        print!("{}", SnapshotDiff::new(&pair[0], &pair[1]));
+    }
+    Ok(0)
+}
+
+fn cmd_solve(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--output", "-o"), ("--threads", "")], &[])?;
+    let filename = match args.positional.as_slice() {
+        [puzzle, filename] if puzzle == "teleporter" => filename,
+        [puzzle, _] => return Err(CliError::Usage(format!("unknown puzzle {}", puzzle))),
+        _ => return Err(CliError::Usage(String::from("usage: solve teleporter <bin>")))
+    };
+    let threads = match args.value("--threads") {
+        Some(n) => n.parse::<usize>().ok().filter(|&n| n > 0)
+            .ok_or_else(|| CliError::Usage(format!("bad --threads value {}", n)))?,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get())
+    };
+    let binary = loader::read_binary(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let check = match TeleporterCheck::find(&binary) {
+        Some(check) => check,
+        None => {
+            eprintln!("{}: no teleporter check found", filename);
+            return Ok(EXIT_FAULT);
+        }
+    };
+    eprintln!("check at {}: fn_{}({}, {}) must return {}", check.call_site, check.function, check.m, check.n, check.expected);
+    let r7 = match solve_teleporter(&check, 0..=32767, threads).first() {
+        Some(&r7) => r7,
+        None => {
+            eprintln!("no value of r7 passes the check");
+            return Ok(EXIT_FAULT);
+        }
+    };
+    println!("r7 = {}", r7);
+    let patch = format!("# skip the teleporter check; set r7 to {} as well\n{}\n", r7, check.patch(&binary));
+    match args.value("--output") {
+        Some(out) => {
+            fs::write(out, patch).map_err(|err| CliError::Io(out.to_string(), err))?;
+            eprintln!("apply with: debug {} --patch {}, then !set 7 {} before using the teleporter", filename, out, r7);
+        },
+        None => print!("{}", patch)
+    }
+    Ok(0)
+}
+
+/// Address 0 plus every `--entry`.
+fn entry_points(args: &Args) -> Result<Vec<u16>, CliError> {
+    let mut entries = vec![0];
+    for entry in args.values("--entry") {
+        entries.push(parse_number(entry).map_err(|_| CliError::Usage(format!("bad --entry address {}", entry)))?);
+    }
+    Ok(entries)
+}
+
+fn cmd_asm(args: &[String]) -> Result<u8, CliError> {
+    let args = Args::parse(args, &[("--output", "-o")], &[])?;
+    let filename = args.file()?;
+    let source = fs::read_to_string(filename).map_err(|err| CliError::Io(filename.to_string(), err))?;
+    let words = asm::assemble(&source).map_err(|err| CliError::Asm(filename.to_string(), err))?;
+    let out = match args.value("--output") {
+        Some(out) => PathBuf::from(out),
+        None => Path::new(filename).with_extension("bin")
+    };
+    loader::write_binary(&out, &words).map_err(|err| CliError::Io(out.display().to_string(), err))?;
+    Ok(0)
+}
@@ -0,0 +1,215 @@
+//! Patch files: replacement words written over the program after it is
+//! loaded, each optionally checked against the words it replaces.
+//!
+//! ```text
+//! # comments run to the end of the line, except inside quotes
+//! 5489: 21 21                    # raw words
+//! 5489 expect 17 1531: 21 21     # refuse to patch unless memory holds 17 1531
+//! 5489 expect call 6027: noop    # either side may be one line of assembly
+//! 5491:                          # an empty right-hand side starts an
+//!     set r0 6                   # indented block of assembly, labels
+//!     set r1 1                   # resolving to absolute addresses
+//! ```
+
+use std::fmt;
+use crate::asm::{assemble_at, strip_comment};
+use crate::breakpoint::parse_number;
+
+/// A patch error and the 1-based line of the patch file it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchError {
+    /// 1-based line of the patch file.
+    pub line: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Words to write over the program at one address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    /// Line of the patch file the patch starts on.
+    pub line: usize,
+    /// First address overwritten.
+    pub addr: u16,
+    /// The replacement words.
+    pub words: Vec<u16>,
+    /// Words that must be in memory at `addr` before patching.
+    pub expect: Option<Vec<u16>>,
+}
+
+/// Writes the patch as a line of a patch file.
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |words: &[u16]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>().join(" ");
+        write!(f, "{}", self.addr)?;
+        if let Some(expect) = &self.expect {
+            write!(f, " expect {}", join(expect))?;
+        }
+        write!(f, ": {}", join(&self.words))
+    }
+}
+
+/// Parses a patch file.
+pub fn parse(source: &str) -> Result<Vec<Patch>, PatchError> {
+    let lines: Vec<&str> = source.lines().map(|line| strip_comment(line, '#')).collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = i + 1;
+        let text = lines[i];
+        i += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let err = |message: String| PatchError { line, message };
+        if text.starts_with(char::is_whitespace) {
+            return Err(err(String::from("indented line outside a patch")));
+        }
+        let (head, body) = text.split_once(':').ok_or_else(|| err(String::from("expected <addr>: <words>")))?;
+        let (addr, expect) = match head.split_once(" expect ") {
+            Some((addr, expect)) => (addr, Some(expect)),
+            None => (head, None)
+        };
+        let addr = parse_number(addr.trim()).map_err(|_| err(format!("bad address '{}'", addr.trim())))?;
+        let expect = expect.map(|expect| words(expect, addr, line)).transpose()?;
+        let words = match body.trim().is_empty() {
+            true => {
+                let start = i;
+                while i < lines.len() && (lines[i].trim().is_empty() || lines[i].starts_with(char::is_whitespace)) {
+                    i += 1;
+                }
+                assemble_at(&lines[start..i].join("\n"), addr)
+                    .map_err(|e| PatchError { line: start + e.line, message: e.message })?
+            },
+            false => words(body, addr, line)?
+        };
+        if words.is_empty() {
+            return Err(err(String::from("patch has no words")));
+        }
+        if addr as usize + words.len().max(expect.as_ref().map_or(0, Vec::len)) > crate::cpu::MEMORY_SIZE {
+            return Err(err(format!("patch at {} runs past the end of memory", addr)));
+        }
+        patches.push(Patch { line, addr, words, expect });
+    }
+    Ok(patches)
+}
+
+/// Numbers if every token is one, otherwise a line of assembly at `addr`.
+fn words(text: &str, addr: u16, line: usize) -> Result<Vec<u16>, PatchError> {
+    let numbers: Result<Vec<u16>, _> = text.split_whitespace().map(parse_number).collect();
+    match numbers {
+        Ok(numbers) => Ok(numbers),
+        Err(_) => assemble_at(text, addr).map_err(|e| PatchError { line, message: e.message })
+    }
+}
+
+/// A patch written to memory, with the words it replaced.
+#[derive(Debug, Clone)]
+pub struct AppliedPatch {
+    /// Number used by `revert` and `reapply`.
+    pub id: usize,
+    /// The patch as parsed.
+    pub patch: Patch,
+    /// The words the patch overwrote.
+    pub original: Vec<u16>,
+    /// False once reverted.
+    pub active: bool,
+}
+
+impl fmt::Display for AppliedPatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |words: &[u16]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>().join(" ");
+        write!(f, "{:>3}  {:<8} {}", self.id, if self.active { "applied" } else { "reverted" }, self.patch.addr)?;
+        if self.patch.words.len() > 1 {
+            write!(f, "..{}", self.patch.addr as usize + self.patch.words.len() - 1)?;
+        }
+        write!(f, "  {} -> {}", join(&self.original), join(&self.patch.words))
+    }
+}
+
+/// The patches applied to a machine, numbered from 1.
+#[derive(Debug, Clone, Default)]
+pub struct Patches {
+    list: Vec<AppliedPatch>,
+}
+
+impl Patches {
+    /// An empty list.
+    pub fn new() -> Patches {
+        Patches::default()
+    }
+
+    /// Checks `patch.expect` and writes the patch into `memory`.
+    pub fn apply(&mut self, patch: Patch, memory: &mut [u16]) -> Result<usize, PatchError> {
+        let addr = patch.addr as usize;
+        let err = |message: String| PatchError { line: patch.line, message };
+        let end = addr + patch.words.len().max(patch.expect.as_ref().map_or(0, Vec::len));
+        if end > memory.len() {
+            return Err(err(format!("patch at {} runs past the end of memory", addr)));
+        }
+        if let Some(expect) = &patch.expect {
+            let found = &memory[addr..addr + expect.len()];
+            if found != expect.as_slice() {
+                return Err(err(format!("expected {:?} at {}, found {:?}", expect, addr, found)));
+            }
+        }
+        let range = addr..addr + patch.words.len();
+        let original = memory[range.clone()].to_vec();
+        memory[range].copy_from_slice(&patch.words);
+        let id = self.list.len() + 1;
+        self.list.push(AppliedPatch { id, patch, original, active: true });
+        Ok(id)
+    }
+
+    /// Puts back the words patch `id` replaced.
+    pub fn revert(&mut self, id: usize, memory: &mut [u16]) -> Result<(), String> {
+        self.swap(id, memory, false)
+    }
+
+    /// Writes a reverted patch again.
+    pub fn reapply(&mut self, id: usize, memory: &mut [u16]) -> Result<(), String> {
+        self.swap(id, memory, true)
+    }
+
+    fn swap(&mut self, id: usize, memory: &mut [u16], active: bool) -> Result<(), String> {
+        let applied = self.list.iter_mut().find(|applied| applied.id == id).ok_or_else(|| format!("no patch {}", id))?;
+        if applied.active == active {
+            return Err(format!("patch {} is already {}", id, if active { "applied" } else { "reverted" }));
+        }
+        let (from, to) = match active {
+            true => (&applied.original, &applied.patch.words),
+            false => (&applied.patch.words, &applied.original)
+        };
+        let range = applied.patch.addr as usize..applied.patch.addr as usize + from.len();
+        if memory[range.clone()] != from[..] {
+            return Err(format!("memory at {} has changed since patch {} was {}", range.start, id,
+                if active { "reverted" } else { "applied" }));
+        }
+        memory[range].copy_from_slice(to);
+        applied.active = active;
+        Ok(())
+    }
+
+    /// Every applied patch, in the order applied.
+    pub fn iter(&self) -> impl Iterator<Item = &AppliedPatch> {
+        self.list.iter()
+    }
+
+    /// Whether no patch has been applied.
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Forgets every patch, leaving memory as it is.
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+}
@@ -0,0 +1,160 @@
+//! Counts how often each address executes and how many instructions each
+//! function accounts for, itself and through the functions it calls.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::cpu::{CPU, MEMORY_SIZE, Observer, StepResult};
+use crate::instruction::decode;
+
+/// Per-function totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionStats {
+    /// Times the function was called.
+    pub calls: u64,
+    /// Instructions executed in the function itself.
+    pub exclusive: u64,
+    /// Instructions executed while the function was on the call stack.
+    /// Recursive activations are only counted once.
+    pub inclusive: u64,
+}
+
+struct Activation {
+    function: u16,
+    entered_at: u64,
+}
+
+/// Attach with [`CPU::run_with`], then print [`Profiler::report`].
+pub struct Profiler {
+    counts: Vec<u64>,
+    total: u64,
+    functions: HashMap<u16, FunctionStats>,
+    stack: Vec<Activation>,
+    /// Activations of each function currently on `stack`.
+    active: HashMap<u16, usize>,
+    pc: Option<u16>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    /// A profiler with nothing counted.
+    pub fn new() -> Profiler {
+        Profiler {
+            counts: vec![0; MEMORY_SIZE],
+            total: 0,
+            functions: HashMap::new(),
+            stack: Vec::new(),
+            active: HashMap::new(),
+            pc: None,
+        }
+    }
+
+    /// Instructions executed so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Times the instruction at `addr` executed.
+    pub fn count(&self, addr: u16) -> u64 {
+        self.counts[addr as usize]
+    }
+
+    /// Totals per function entry, including functions still running.
+    pub fn functions(&self) -> HashMap<u16, FunctionStats> {
+        let mut functions = self.functions.clone();
+        let mut seen = HashSet::new();
+        for activation in &self.stack {
+            if seen.insert(activation.function) {
+                functions.entry(activation.function).or_default().inclusive += self.total - activation.entered_at;
+            }
+        }
+        functions
+    }
+
+    fn enter(&mut self, function: u16) {
+        self.functions.entry(function).or_default().calls += 1;
+        *self.active.entry(function).or_default() += 1;
+        self.stack.push(Activation { function, entered_at: self.total });
+    }
+
+    fn leave(&mut self) {
+        let activation = match self.stack.pop() {
+            Some(activation) => activation,
+            None => return
+        };
+        let active = self.active.get_mut(&activation.function).expect("active function");
+        *active -= 1;
+        if *active == 0 {
+            self.functions.entry(activation.function).or_default().inclusive += self.total - activation.entered_at;
+        }
+    }
+
+    /// The `top` hottest addresses and functions as a table. `cpu` supplies
+    /// the disassembly.
+    pub fn report(&self, cpu: &CPU, top: usize) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        let mut out = String::new();
+        writeln!(out, "{} instructions executed", self.total).unwrap();
+
+        let mut addresses: Vec<(usize, u64)> = self.counts.iter().copied().enumerate().filter(|&(_, count)| count > 0).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(out, "\nhot addresses").unwrap();
+        writeln!(out, "{:>12} {:>7}  {:>5}  instruction", "count", "%", "addr").unwrap();
+        for &(addr, count) in addresses.iter().take(top) {
+            let text = decode(cpu.memory(), addr).map(|ins| ins.to_string()).unwrap_or_default();
+            writeln!(out, "{:>12} {:>6.2}%  {:>5}  {}", count, percent(count), addr, text).unwrap();
+        }
+
+        let mut functions: Vec<(u16, FunctionStats)> = self.functions().into_iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(&b.0)));
+        writeln!(out, "\nfunctions").unwrap();
+        writeln!(out, "{:>10} {:>12} {:>7} {:>12} {:>7}  function", "calls", "exclusive", "%", "inclusive", "%").unwrap();
+        for &(function, stats) in functions.iter().take(top) {
+            writeln!(out, "{:>10} {:>12} {:>6.2}% {:>12} {:>6.2}%  fn_{}",
+                stats.calls, stats.exclusive, percent(stats.exclusive), stats.inclusive, percent(stats.inclusive), function).unwrap();
+        }
+        out
+    }
+}
+
+impl Observer for Profiler {
+    fn before_step(&mut self, cpu: &CPU) {
+        if self.stack.is_empty() {
+            // Whatever is running when profiling starts is the root function.
+            self.enter(cpu.pc() as u16);
+        }
+        self.pc = match cpu.is_halted() {
+            true => None,
+            false => Some(cpu.pc() as u16)
+        };
+    }
+
+    fn after_step(&mut self, cpu: &CPU, result: StepResult) {
+        if matches!(result, StepResult::NeedsInput | StepResult::Fault(_)) {
+            return;
+        }
+        let pc = match self.pc.take() {
+            Some(pc) => pc,
+            None => return
+        };
+        self.counts[pc as usize] += 1;
+        self.total += 1;
+        if let Some(activation) = self.stack.last() {
+            self.functions.entry(activation.function).or_default().exclusive += 1;
+        }
+        // Follow the machine's shadow call stack; the root activation sits below it.
+        let depth = cpu.call_stack().len() + 1;
+        while self.stack.len() > depth {
+            self.leave();
+        }
+        if self.stack.len() < depth {
+            for frame in &cpu.call_stack()[self.stack.len() - 1..] {
+                self.enter(frame.function);
+            }
+        }
+    }
+}
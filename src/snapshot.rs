@@ -0,0 +1,335 @@
+//! Save files holding a complete machine state, and named slots of them.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cpu::MEMORY_SIZE;
+
+/// Identifies a save file. Followed by a little-endian `u16` format version.
+pub const MAGIC: &[u8; 8] = b"SYNSAVE\0";
+/// Format version this build reads and writes.
+pub const VERSION: u16 = 1;
+
+/// File extension used for save slots.
+pub const SLOT_EXTENSION: &str = "sav";
+
+const FLAG_HALTED: u16 = 1;
+
+/// A complete copy of the machine, as produced by `CPU::snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    /// All 32768 words of memory.
+    pub memory: Vec<u16>,
+    /// Registers r0-r7.
+    pub registers: [u16; 8],
+    /// The stack, top last.
+    pub stack: Vec<u16>,
+    /// Characters queued for `IN`.
+    pub input_queue: Vec<u16>,
+    /// Address of the next instruction.
+    pub pc: u16,
+    /// Whether the program had halted.
+    pub halted: bool,
+    /// Seconds since the Unix epoch when the snapshot was taken.
+    pub saved_at: u64,
+    /// The last non-empty line the program printed before the snapshot.
+    pub last_output: String,
+}
+
+/// Why a save file could not be read or written.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The file could not be read or written.
+    Io(io::Error),
+    /// The file does not start with [`MAGIC`].
+    BadMagic,
+    /// The file has a version this build does not read.
+    UnsupportedVersion(u16),
+    /// The file ends early.
+    Truncated,
+    /// The checksum does not match the contents.
+    ChecksumMismatch {
+        /// Checksum written in the file.
+        stored: u32,
+        /// Checksum of what the file holds.
+        computed: u32,
+    },
+    /// The file holds this many memory words instead of [`MEMORY_SIZE`].
+    BadMemorySize(usize),
+    /// A slot name with characters other than letters, digits, `-` and `_`.
+    BadSlotName(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::BadMagic => write!(f, "not a save file"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported save file version {}", version),
+            SnapshotError::Truncated => write!(f, "save file is truncated"),
+            SnapshotError::ChecksumMismatch { stored, computed } =>
+                write!(f, "save file is corrupt (checksum {:08x}, expected {:08x})", computed, stored),
+            SnapshotError::BadMemorySize(len) => write!(f, "save file has {} words of memory, expected {}", len, MEMORY_SIZE),
+            SnapshotError::BadSlotName(name) => write!(f, "bad save name '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl SaveState {
+    /// Serializes the state. Layout, all little-endian:
+    ///
+    /// ```text
+    /// magic[8] version:u16 flags:u16 pc:u16 registers:[u16; 8]
+    /// saved_at:u64 last_output_len:u32 last_output:[u8]
+    /// memory_len:u32 memory:[u16] stack_len:u32 stack:[u16]
+    /// input_len:u32 input:[u16] checksum:u32
+    /// ```
+    ///
+    /// The checksum is FNV-1a over every preceding byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(32 + 2 * (self.memory.len() + self.stack.len() + self.input_queue.len()));
+        out.extend_from_slice(MAGIC);
+        put_u16(&mut out, VERSION);
+        put_u16(&mut out, if self.halted { FLAG_HALTED } else { 0 });
+        put_u16(&mut out, self.pc);
+        for &reg in &self.registers {
+            put_u16(&mut out, reg);
+        }
+        out.extend_from_slice(&self.saved_at.to_le_bytes());
+        out.extend_from_slice(&(self.last_output.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.last_output.as_bytes());
+        put_words(&mut out, &self.memory);
+        put_words(&mut out, &self.stack);
+        put_words(&mut out, &self.input_queue);
+        let checksum = fnv1a(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Parses the bytes written by [`SaveState::to_bytes`], verifying the checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, SnapshotError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if bytes.len() < MAGIC.len() + 2 + 4 {
+            return Err(SnapshotError::Truncated);
+        }
+        let (body, stored) = bytes.split_at(bytes.len() - 4);
+        let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+        let mut reader = Reader { bytes: body, pos: MAGIC.len() };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let computed = fnv1a(body);
+        if computed != stored {
+            return Err(SnapshotError::ChecksumMismatch { stored, computed });
+        }
+
+        let flags = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut registers = [0; 8];
+        for reg in registers.iter_mut() {
+            *reg = reader.u16()?;
+        }
+        let saved_at = reader.u64()?;
+        let len = reader.u32()? as usize;
+        let last_output = String::from_utf8_lossy(reader.take(len)?).into_owned();
+        let memory = reader.words()?;
+        if memory.len() != MEMORY_SIZE {
+            return Err(SnapshotError::BadMemorySize(memory.len()));
+        }
+        let stack = reader.words()?;
+        let input_queue = reader.words()?;
+        Ok(SaveState {
+            memory,
+            registers,
+            stack,
+            input_queue,
+            pc,
+            halted: flags & FLAG_HALTED != 0,
+            saved_at,
+            last_output
+        })
+    }
+
+    /// Writes the state to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Reads a state written by [`SaveState::save`].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SaveState, SnapshotError> {
+        SaveState::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Summary of one save slot, as listed by [`SaveSlots::list`].
+#[derive(Debug, Clone)]
+pub struct SlotInfo {
+    /// Slot name, without the extension.
+    pub name: String,
+    /// Seconds since the Unix epoch when it was saved.
+    pub saved_at: u64,
+    /// Program counter at the time.
+    pub pc: u16,
+    /// The last line the program had printed.
+    pub last_output: String,
+}
+
+/// A directory of named save files, one `<name>.sav` per slot.
+pub struct SaveSlots {
+    dir: PathBuf,
+}
+
+impl SaveSlots {
+    /// Slots kept in `dir`, which is created on the first save.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> SaveSlots {
+        SaveSlots { dir: dir.into() }
+    }
+
+    /// The directory holding the slots.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the file backing `name`. Names are limited to letters, digits, `-` and `_`.
+    pub fn path(&self, name: &str) -> Result<PathBuf, SnapshotError> {
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(SnapshotError::BadSlotName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.{}", name, SLOT_EXTENSION)))
+    }
+
+    /// Writes `state` to the slot, creating the directory if needed.
+    pub fn save(&self, name: &str, state: &SaveState) -> Result<(), SnapshotError> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        state.save(path)
+    }
+
+    /// Reads the state in slot `name`.
+    pub fn load(&self, name: &str) -> Result<SaveState, SnapshotError> {
+        SaveState::load(self.path(name)?)
+    }
+
+    /// Deletes slot `name`.
+    pub fn delete(&self, name: &str) -> Result<(), SnapshotError> {
+        fs::remove_file(self.path(name)?)?;
+        Ok(())
+    }
+
+    /// Every readable slot, newest first. A missing directory is an empty list.
+    pub fn list(&self) -> Result<Vec<SlotInfo>, SnapshotError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into())
+        };
+        let mut slots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SLOT_EXTENSION) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue
+            };
+            if let Ok(state) = SaveState::load(&path) {
+                slots.push(SlotInfo { name, saved_at: state.saved_at, pc: state.pc, last_output: state.last_output });
+            }
+        }
+        slots.sort_by(|a, b| b.saved_at.cmp(&a.saved_at).then_with(|| a.name.cmp(&b.name)));
+        Ok(slots)
+    }
+}
+
+/// Current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` UTC.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil-from-days, from Howard Hinnant's date algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_words(out: &mut Vec<u8>, words: &[u16]) {
+    out.extend_from_slice(&(words.len() as u32).to_le_bytes());
+    for &word in words {
+        put_u16(out, word);
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for &byte in bytes {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], SnapshotError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(SnapshotError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let b = self.take(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn words(&mut self) -> Result<Vec<u16>, SnapshotError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len.checked_mul(2).ok_or(SnapshotError::Truncated)?)?;
+        Ok(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect())
+    }
+}
@@ -0,0 +1,106 @@
+//! Solvers for puzzles that are impractical to brute-force inside the VM.
+
+use std::ops::RangeInclusive;
+use std::thread;
+use crate::hook::teleporter;
+use crate::instruction::{decode, Opcode, Operand};
+use crate::patch::Patch;
+
+/// The teleporter confirmation: `SET r0 m; SET r1 n; CALL function;
+/// EQ reg r0 expected`, which only passes when `function(m, n)` returns
+/// `expected` for the value in r7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TeleporterCheck {
+    /// Address of the `CALL`.
+    pub call_site: u16,
+    /// Address the `CALL` goes to.
+    pub function: u16,
+    /// Value put in r0 before the call.
+    pub m: u16,
+    /// Value put in r1 before the call.
+    pub n: u16,
+    /// Register the `EQ` stores its verdict in.
+    pub result: u8,
+    /// Value r0 must hold after the call.
+    pub expected: u16,
+}
+
+impl TeleporterCheck {
+    /// Scans `memory` for the call sequence, accepting only a callee that
+    /// reads r7 the way the confirmation routine does.
+    pub fn find(memory: &[u16]) -> Option<TeleporterCheck> {
+        (0..memory.len()).find_map(|addr| TeleporterCheck::at(memory, addr))
+    }
+
+    fn at(memory: &[u16], addr: usize) -> Option<TeleporterCheck> {
+        let set_m = decode(memory, addr).ok()?;
+        let set_n = decode(memory, addr + set_m.len).ok()?;
+        let call_site = addr + set_m.len + set_n.len;
+        let call = decode(memory, call_site).ok()?;
+        let eq = decode(memory, call_site + call.len).ok()?;
+        let (m, n, function, result, expected) = match (set_m.op, set_m.operands, set_n.op, set_n.operands, call.op, call.operands, eq.op, eq.operands) {
+            (
+                Opcode::Set, [Operand::Register(0), Operand::Literal(m), _],
+                Opcode::Set, [Operand::Register(1), Operand::Literal(n), _],
+                Opcode::Call, [Operand::Literal(function), _, _],
+                Opcode::Eq, [Operand::Register(result), Operand::Register(0), Operand::Literal(expected)]
+            ) => (m, n, function, result, expected),
+            _ => return None
+        };
+        if !reads_r7(memory, function as usize) {
+            return None;
+        }
+        Some(TeleporterCheck { call_site: call_site as u16, function, m, n, result, expected })
+    }
+
+    /// Whether `r7` passes the check.
+    pub fn passes(&self, r7: u16) -> bool {
+        teleporter(self.m, self.n, r7) == self.expected
+    }
+
+    /// Replaces the `CALL` and `EQ` with `SET r0 expected; SET reg 1`, which
+    /// is what the check leaves behind when it passes, without running it.
+    pub fn patch(&self, memory: &[u16]) -> Patch {
+        let start = self.call_site as usize;
+        let reg = 32768 + self.result as u16;
+        Patch {
+            line: 0,
+            addr: self.call_site,
+            words: vec![Opcode::Set as u16, 32768, self.expected, Opcode::Set as u16, reg, 1],
+            expect: Some(memory[start..start + 6].to_vec()),
+        }
+    }
+}
+
+/// Whether one of the first few instructions of `function` is `SET r1 r7`.
+fn reads_r7(memory: &[u16], function: usize) -> bool {
+    let mut addr = function;
+    for _ in 0..16 {
+        let ins = match decode(memory, addr) {
+            Ok(ins) => ins,
+            Err(_) => return false
+        };
+        if ins.op == Opcode::Set && ins.operands[..2] == [Operand::Register(1), Operand::Register(7)] {
+            return true;
+        }
+        addr += ins.len;
+    }
+    false
+}
+
+/// Every value of r7 in `candidates` that passes `check`, tried on `threads`
+/// threads.
+pub fn solve_teleporter(check: &TeleporterCheck, candidates: RangeInclusive<u16>, threads: usize) -> Vec<u16> {
+    let threads = threads.max(1);
+    let mut found: Vec<u16> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let candidates = candidates.clone();
+                scope.spawn(move || candidates.skip(t).step_by(threads).filter(|&r7| check.passes(r7)).collect::<Vec<u16>>())
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().expect("solver thread panicked")).collect()
+    });
+    found.sort_unstable();
+    found
+}
@@ -0,0 +1,381 @@
+//! Structured execution traces, written as JSON Lines or a compact binary
+//! format while the machine runs.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use crate::cpu::{CPU, Observer, StepResult};
+use crate::instruction::{decode, Instruction, Opcode, Operand};
+
+/// Identifies a binary trace. Followed by a little-endian `u16` version.
+pub const MAGIC: &[u8; 8] = b"SYNTRACE";
+/// Format version this build reads and writes.
+pub const VERSION: u16 = 1;
+
+const FLAG_STACK: u8 = 1;
+const FLAG_MEMORY: u8 = 2;
+const FLAG_OUTPUT: u8 = 4;
+const FLAG_HOOK: u8 = 8;
+
+/// How a trace is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line.
+    Jsonl,
+    /// [`MAGIC`], a version, then packed records; see [`TraceRecord::write_binary`].
+    Binary,
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one since tracing began.
+    pub step: u64,
+    /// Address of the instruction.
+    pub pc: u16,
+    /// The instruction executed.
+    pub ins: Instruction,
+    /// Value of each operand before execution; registers are read.
+    pub values: Vec<u16>,
+    /// `(register, old, new)` for every register that changed.
+    pub registers: Vec<(u8, u16, u16)>,
+    /// Stack depth and top value afterwards, if either changed.
+    pub stack: Option<(u32, Option<u16>)>,
+    /// `(address, old, new)` of a `WMEM`.
+    pub memory: Option<(u16, u16, u16)>,
+    /// Character printed by `OUT`.
+    pub output: Option<char>,
+    /// Address of the native hook a `CALL` ran instead of the subroutine.
+    pub hook: Option<u16>,
+}
+
+impl TraceRecord {
+    /// The record as one line of JSON, without the newline.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(out, "{{\"step\":{},\"pc\":{},\"op\":\"{}\",\"args\":[", self.step, self.pc, self.ins.op).unwrap();
+        let args: Vec<String> = self.ins.args().iter().map(|arg| format!("\"{}\"", arg)).collect();
+        out.push_str(&args.join(","));
+        out.push_str("],\"values\":[");
+        let values: Vec<String> = self.values.iter().map(|value| value.to_string()).collect();
+        out.push_str(&values.join(","));
+        out.push(']');
+        if !self.registers.is_empty() {
+            let regs: Vec<String> = self.registers.iter().map(|(reg, old, new)| format!("[{},{},{}]", reg, old, new)).collect();
+            write!(out, ",\"regs\":[{}]", regs.join(",")).unwrap();
+        }
+        if let Some((depth, top)) = self.stack {
+            match top {
+                Some(top) => write!(out, ",\"stack\":{{\"depth\":{},\"top\":{}}}", depth, top).unwrap(),
+                None => write!(out, ",\"stack\":{{\"depth\":{},\"top\":null}}", depth).unwrap()
+            }
+        }
+        if let Some((addr, old, new)) = self.memory {
+            write!(out, ",\"mem\":[{},{},{}]", addr, old, new).unwrap();
+        }
+        if let Some(c) = self.output {
+            out.push_str(",\"out\":\"");
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                c if (c as u32) < 0x20 || (c as u32) >= 0x7f => write!(out, "\\u{:04x}", c as u32).unwrap(),
+                c => out.push(c)
+            }
+            out.push('"');
+        }
+        if let Some(hook) = self.hook {
+            write!(out, ",\"hook\":{}", hook).unwrap();
+        }
+        out.push('}');
+        out
+    }
+
+    /// Packed little-endian layout:
+    ///
+    /// ```text
+    /// step:u64 pc:u16 opcode:u8 flags:u8 operands:[u16; arity] values:[u16; arity]
+    /// reg_count:u8 (reg:u8 old:u16 new:u16)*
+    /// [depth:u32 has_top:u8 top:u16]  if flags & 1
+    /// [addr:u16 old:u16 new:u16]      if flags & 2
+    /// [char:u16]                      if flags & 4
+    /// [hook:u16]                      if flags & 8
+    /// ```
+    pub fn write_binary(&self, out: &mut Vec<u8>) {
+        let flags = (self.stack.is_some() as u8 * FLAG_STACK)
+            | (self.memory.is_some() as u8 * FLAG_MEMORY)
+            | (self.output.is_some() as u8 * FLAG_OUTPUT)
+            | (self.hook.is_some() as u8 * FLAG_HOOK);
+        out.extend_from_slice(&self.step.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.push(self.ins.op as u8);
+        out.push(flags);
+        for arg in self.ins.args() {
+            let word = match *arg {
+                Operand::Literal(value) => value,
+                Operand::Register(reg) => 32768 + reg as u16,
+            };
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        for value in &self.values {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.push(self.registers.len() as u8);
+        for &(reg, old, new) in &self.registers {
+            out.push(reg);
+            out.extend_from_slice(&old.to_le_bytes());
+            out.extend_from_slice(&new.to_le_bytes());
+        }
+        if let Some((depth, top)) = self.stack {
+            out.extend_from_slice(&depth.to_le_bytes());
+            out.push(top.is_some() as u8);
+            out.extend_from_slice(&top.unwrap_or(0).to_le_bytes());
+        }
+        if let Some((addr, old, new)) = self.memory {
+            for word in [addr, old, new] {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        if let Some(c) = self.output {
+            out.extend_from_slice(&(c as u16).to_le_bytes());
+        }
+        if let Some(hook) = self.hook {
+            out.extend_from_slice(&hook.to_le_bytes());
+        }
+    }
+}
+
+/// Reads back a binary trace written by [`Tracer`].
+pub fn read_binary(bytes: &[u8]) -> Result<Vec<TraceRecord>, String> {
+    if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(String::from("not a binary trace"));
+    }
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if version != VERSION {
+        return Err(format!("unsupported trace version {}", version));
+    }
+    let mut reader = Reader { bytes, pos: MAGIC.len() + 2 };
+    let mut records = Vec::new();
+    while reader.pos < bytes.len() {
+        records.push(reader.record().ok_or_else(|| format!("trace is truncated after {} records", records.len()))?);
+    }
+    Ok(records)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.pos..self.pos + N)?;
+        self.pos += N;
+        bytes.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn record(&mut self) -> Option<TraceRecord> {
+        let step = u64::from_le_bytes(self.take()?);
+        let pc = self.u16()?;
+        let op = Opcode::from_u16(self.u8()? as u16)?;
+        let flags = self.u8()?;
+        let mut memory = [op as u16, 0, 0, 0];
+        for word in memory.iter_mut().skip(1).take(op.arity()) {
+            *word = self.u16()?;
+        }
+        let ins = decode(&memory, 0).ok()?;
+        let values = (0..op.arity()).map(|_| self.u16()).collect::<Option<Vec<u16>>>()?;
+        let registers = (0..self.u8()?)
+            .map(|_| Some((self.u8()?, self.u16()?, self.u16()?)))
+            .collect::<Option<Vec<_>>>()?;
+        let stack = match flags & FLAG_STACK {
+            0 => None,
+            _ => {
+                let depth = u32::from_le_bytes(self.take()?);
+                let has_top = self.u8()? != 0;
+                let top = self.u16()?;
+                Some((depth, if has_top { Some(top) } else { None }))
+            }
+        };
+        let memory = match flags & FLAG_MEMORY {
+            0 => None,
+            _ => Some((self.u16()?, self.u16()?, self.u16()?))
+        };
+        let output = match flags & FLAG_OUTPUT {
+            0 => None,
+            _ => Some(char::from_u32(self.u16()? as u32)?)
+        };
+        let hook = match flags & FLAG_HOOK {
+            0 => None,
+            _ => Some(self.u16()?)
+        };
+        Some(TraceRecord { step, pc, ins, values, registers, stack, memory, output, hook })
+    }
+}
+
+/// Which executed instructions end up in the trace. Empty filters match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only instructions whose address lies in one of these ranges.
+    pub addresses: Vec<RangeInclusive<u16>>,
+    /// Only instructions with one of these opcodes.
+    pub opcodes: Vec<Opcode>,
+    /// Only steps in this window, counted from the start of tracing.
+    pub steps: Option<RangeInclusive<u64>>,
+}
+
+impl Filter {
+    /// Whether the step is traced.
+    pub fn matches(&self, step: u64, pc: u16, op: Opcode) -> bool {
+        (self.addresses.is_empty() || self.addresses.iter().any(|range| range.contains(&pc)))
+            && (self.opcodes.is_empty() || self.opcodes.contains(&op))
+            && self.steps.as_ref().is_none_or(|steps| steps.contains(&step))
+    }
+}
+
+/// The machine state a record is diffed against.
+struct Before {
+    pc: u16,
+    ins: Instruction,
+    values: Vec<u16>,
+    registers: [u16; 8],
+    depth: usize,
+    top: Option<u16>,
+    /// Address and old value of the word a `WMEM` is about to overwrite.
+    memory: Option<(u16, u16)>,
+}
+
+/// Records every step the machine executes to `writer`. Attach it with
+/// [`CPU::run_with`] and call [`Tracer::finish`] afterwards.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: Format,
+    filter: Filter,
+    step: u64,
+    before: Option<Before>,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Traces to `writer`; a binary trace starts with its header right away.
+    pub fn new(mut writer: W, format: Format, filter: Filter) -> Tracer<W> {
+        let mut error = None;
+        if format == Format::Binary {
+            let mut header = MAGIC.to_vec();
+            header.extend_from_slice(&VERSION.to_le_bytes());
+            error = writer.write_all(&header).err();
+        }
+        Tracer { writer, format, filter, step: 0, before: None, buffer: Vec::new(), error }
+    }
+
+    /// Instructions executed so far, traced or not.
+    pub fn steps(&self) -> u64 {
+        self.step
+    }
+
+    /// Flushes the writer and returns it, or the first write error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buffer.clear();
+        match self.format {
+            Format::Jsonl => {
+                self.buffer.extend_from_slice(record.to_json().as_bytes());
+                self.buffer.push(b'\n');
+            },
+            Format::Binary => record.write_binary(&mut self.buffer)
+        }
+        self.error = self.writer.write_all(&self.buffer).err();
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn before_step(&mut self, cpu: &CPU) {
+        self.before = None;
+        if cpu.is_halted() {
+            return;
+        }
+        let ins = match decode(cpu.memory(), cpu.pc()) {
+            Ok(ins) => ins,
+            Err(_) => return
+        };
+        if !self.filter.matches(self.step, cpu.pc() as u16, ins.op) {
+            return;
+        }
+        let registers = *cpu.registers();
+        let value = |arg: &Operand| match *arg {
+            Operand::Literal(value) => value,
+            Operand::Register(reg) => registers[reg as usize],
+        };
+        let values: Vec<u16> = ins.args().iter().map(value).collect();
+        let memory = match ins.op {
+            Opcode::Wmem => cpu.memory().get(values[0] as usize).map(|&old| (values[0], old)),
+            _ => None
+        };
+        self.before = Some(Before {
+            pc: cpu.pc() as u16,
+            ins,
+            values,
+            registers,
+            depth: cpu.stack().len(),
+            top: cpu.stack().last().copied(),
+            memory,
+        });
+    }
+
+    fn after_step(&mut self, cpu: &CPU, result: StepResult) {
+        if matches!(result, StepResult::NeedsInput | StepResult::Fault(_)) {
+            return;
+        }
+        let step = self.step;
+        self.step += 1;
+        let before = match self.before.take() {
+            Some(before) => before,
+            None => return
+        };
+        let registers = before.registers.iter().zip(cpu.registers())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (&old, &new))| (reg as u8, old, new))
+            .collect();
+        let top = cpu.stack().last().copied();
+        let stack = match cpu.stack().len() != before.depth || top != before.top {
+            true => Some((cpu.stack().len() as u32, top)),
+            false => None
+        };
+        let memory = before.memory.map(|(addr, old)| (addr, old, cpu.memory()[addr as usize]));
+        let output = match result {
+            StepResult::Output(c) => Some(c),
+            _ => None
+        };
+        let record = TraceRecord {
+            step,
+            pc: before.pc,
+            ins: before.ins,
+            values: before.values,
+            registers,
+            stack,
+            memory,
+            output,
+            hook: cpu.last_hook()
+        };
+        self.write(&record);
+    }
+}
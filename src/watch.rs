@@ -0,0 +1,171 @@
+//! Watchpoints on memory reads, writes and execution.
+
+use std::fmt;
+use crate::cpu::Frame;
+
+/// The kind of memory access a watchpoint reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// `RMEM` loaded the word.
+    Read,
+    /// `WMEM` stored to the word.
+    Write,
+    /// An instruction starting at the address was executed.
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Execute => "execute",
+        })
+    }
+}
+
+/// Watches a range of addresses for reads, writes or execution.
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    /// Number used by the console commands.
+    pub id: usize,
+    /// First watched address.
+    pub start: u16,
+    /// Last watched address, inclusive.
+    pub end: u16,
+    /// Fire on `RMEM` from the range.
+    pub read: bool,
+    /// Fire on `WMEM` to the range.
+    pub write: bool,
+    /// Fire on executing an instruction that starts in the range.
+    pub execute: bool,
+    /// Stop in the debugger when the watchpoint fires, instead of only reporting.
+    pub stop: bool,
+    /// Disabled watchpoints neither count hits nor fire.
+    pub enabled: bool,
+    /// Times the watchpoint fired.
+    pub hits: u64,
+}
+
+impl Watchpoint {
+    /// Whether an `access` to `addr` fires this watchpoint.
+    pub fn triggers(&self, addr: u16, access: Access) -> bool {
+        self.enabled && (self.start..=self.end).contains(&addr) && match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut kinds = String::new();
+        for (on, c) in [(self.read, 'r'), (self.write, 'w'), (self.execute, 'x')] {
+            kinds.push(if on { c } else { '-' });
+        }
+        write!(f, "{:>3}  {:<8} {}", self.id, if self.enabled { "enabled" } else { "disabled" }, self.start)?;
+        if self.end != self.start {
+            write!(f, "..{}", self.end)?;
+        }
+        write!(f, "  {}  hits {}", kinds, self.hits)?;
+        if self.stop {
+            write!(f, "  break")?;
+        }
+        Ok(())
+    }
+}
+
+/// One watchpoint firing. Reads report the loaded value as both `old` and `new`.
+#[derive(Debug, Clone)]
+pub struct WatchHit {
+    /// The watchpoint that fired.
+    pub id: usize,
+    /// The kind of access.
+    pub access: Access,
+    /// The address accessed.
+    pub addr: u16,
+    /// The word before the access.
+    pub old: u16,
+    /// The word after the access.
+    pub new: u16,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+    /// The shadow call stack at the access, innermost last.
+    pub call_stack: Vec<Frame>,
+    /// The watchpoint asked to stop.
+    pub stop: bool,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "watchpoint {}: {} {} at pc {}", self.id, self.access, self.addr, self.pc)?;
+        match self.access {
+            Access::Write => write!(f, ": {} -> {}", self.old, self.new)?,
+            Access::Read => write!(f, ": {}", self.new)?,
+            Access::Execute => {}
+        }
+        if !self.call_stack.is_empty() {
+            write!(f, "\n    call stack:")?;
+            for frame in self.call_stack.iter().rev() {
+                write!(f, " {}", frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The table of watchpoints consulted by the CPU on every memory access.
+#[derive(Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Watchpoints {
+    /// An empty table.
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    /// Adds an enabled watchpoint on `start..=end` and returns its id.
+    pub fn add(&mut self, start: u16, end: u16, read: bool, write: bool, execute: bool, stop: bool) -> usize {
+        self.next_id += 1;
+        self.list.push(Watchpoint { id: self.next_id, start, end, read, write, execute, stop, enabled: true, hits: 0 });
+        self.next_id
+    }
+
+    /// Deletes watchpoint `id`; false if there is none.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|watch| watch.id != id);
+        self.list.len() != len
+    }
+
+    /// Watchpoint `id`, to change its range or state.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.list.iter_mut().find(|watch| watch.id == id)
+    }
+
+    /// Every watchpoint, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter()
+    }
+
+    /// Whether there are no watchpoints.
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Counts a hit on every watchpoint covering the access and returns
+    /// `(id, stop)` for each of them.
+    pub(crate) fn trigger(&mut self, addr: u16, access: Access) -> Vec<(usize, bool)> {
+        self.list.iter_mut()
+            .filter(|watch| watch.triggers(addr, access))
+            .map(|watch| {
+                watch.hits += 1;
+                (watch.id, watch.stop)
+            })
+            .collect()
+    }
+}
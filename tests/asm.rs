@@ -0,0 +1,61 @@
+use synacor::asm::{assemble, assemble_at, AsmError};
+use synacor::{loader, StepResult};
+
+mod common;
+
+fn error(source: &str) -> AsmError {
+    assemble(source).unwrap_err()
+}
+
+#[test]
+fn encodes_mnemonics_registers_and_literals() {
+    let words = assemble("set r0 'a'\nADD R7, r0, 0x10\nout 10\nhalt").unwrap();
+    assert_eq!(words, [1, 32768, 97, 9, 32775, 32768, 16, 19, 10, 0]);
+}
+
+#[test]
+fn labels_resolve_forwards_and_backwards() {
+    let source = "\
+start:  jmp end     ; forward
+        noop
+end:    jmp start   ; backward
+";
+    assert_eq!(assemble(source).unwrap(), [6, 3, 21, 6, 0]);
+    assert_eq!(assemble_at(source, 100).unwrap(), [6, 103, 21, 6, 100]);
+}
+
+#[test]
+fn data_and_string_directives() {
+    let words = assemble("msg: .string \"a;b\\n\"\n.data 65535, msg, ';', '\\''").unwrap();
+    assert_eq!(words, [97, 59, 98, 10, 65535, 0, 59, 39]);
+}
+
+#[test]
+fn assembled_program_runs() {
+    let source = "\
+        set r1 text
+loop:   rmem r0 r1
+        jf r0 done
+        out r0
+        add r1 r1 1
+        jmp loop
+done:   halt
+text:   .string \"ok\\n\"
+        .data 0
+";
+    let image = loader::parse_binary(&loader::to_bytes(&assemble(source).unwrap()));
+    let (mut cpu, output) = common::machine("");
+    cpu.load(&image);
+    assert_eq!(cpu.run(), StepResult::Halted);
+    assert_eq!(output.contents(), "ok\n");
+}
+
+#[test]
+fn errors_carry_line_numbers() {
+    assert_eq!(error("noop\nfrob r0").line, 2);
+    assert_eq!(error("noop\n\njmp nowhere").message, "undefined label 'nowhere'");
+    assert_eq!(error("set r0").message, "SET takes 2 operands, found 1");
+    assert_eq!(error("out 32768").message, "literal 32768 is larger than 32767");
+    assert_eq!(error("a: noop\na: noop").line, 2);
+    assert_eq!(error("out 'ab'").message, "bad character literal 'ab'");
+}
@@ -0,0 +1,72 @@
+use synacor::breakpoint::{parse_number, Breakpoints, Condition};
+use synacor::CPU;
+
+mod common;
+
+/// r0 = 5, r1 = 0, r2 = 100, [100] = 7, [200] = 9, two values on the stack.
+fn cpu() -> CPU {
+    let (mut cpu, _) = common::machine("halt\n");
+    let mut state = cpu.snapshot();
+    state.registers[0] = 5;
+    state.registers[2] = 100;
+    state.memory[100] = 7;
+    state.memory[200] = 9;
+    state.stack = vec![1, 2];
+    cpu.restore(&state);
+    cpu
+}
+
+fn eval(source: &str) -> bool {
+    Condition::parse(source).unwrap().eval(&cpu())
+}
+
+#[test]
+fn and_binds_tighter_than_or() {
+    assert!(eval("r0 == 5 || r0 == 1 && r1 == 1"));
+    assert!(!eval("r0 == 1 && r1 == 0 || r1 == 1"));
+    assert!(eval("r0 == 1 && r1 == 1 || r2 == 100"));
+}
+
+#[test]
+fn reads_memory_and_depth() {
+    assert!(eval("[100] == 7"));
+    assert!(eval("[r2] == 7"));
+    assert!(eval("[0xc8] == 9"));
+    assert!(eval("depth == 2"));
+    assert!(!eval("depth > 2"));
+    assert_eq!(parse_number("0xc8"), Ok(200));
+    assert!(Condition::parse("[r9] == 1").is_err());
+}
+
+#[test]
+fn tells_two_character_operators_apart() {
+    assert!(eval("r0 <= 5"));
+    assert!(!eval("r0 < 5"));
+    assert!(eval("r0 >= 5"));
+    assert!(!eval("r0 > 5"));
+    assert!(eval("r0 != 4"));
+}
+
+#[test]
+fn bare_values_mean_not_zero() {
+    assert!(eval("r0"));
+    assert!(!eval("r1"));
+    assert!(eval("[r2]"));
+    assert!(Condition::parse("r0 == ").is_err());
+}
+
+#[test]
+fn ignore_counts_let_hits_pass() {
+    let cpu = cpu();
+    let mut breakpoints = Breakpoints::new();
+    let id = breakpoints.add(0, Some(Condition::parse("r0 == 5").unwrap()));
+    let skipped = breakpoints.add(0, Some(Condition::parse("r1").unwrap()));
+    breakpoints.get_mut(id).unwrap().ignore_count = 2;
+    assert_eq!(breakpoints.check(&cpu), None);
+    assert_eq!(breakpoints.check(&cpu), None);
+    assert_eq!(breakpoints.matches(&cpu), Some(id));
+    assert_eq!(breakpoints.check(&cpu), Some(id));
+    let counts: Vec<(u64, u64)> = breakpoints.iter().map(|bp| (bp.hits, bp.ignore_count)).collect();
+    assert_eq!(counts, vec![(3, 0), (0, 0)]);
+    assert!(breakpoints.remove(skipped));
+}
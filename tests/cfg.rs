@@ -0,0 +1,39 @@
+use synacor::asm::assemble;
+use synacor::cfg::{Edge, EdgeKind, Program};
+use synacor::disasm::analyze;
+
+const SOURCE: &str = "\
+        set r0 3        ; 0
+loop:   add r0 r0 32767 ; 3
+        jf r0 done      ; 7
+        call helper     ; 10
+        jmp loop        ; 12
+done:   halt            ; 14
+helper: jmp shared      ; 15
+shared: ret             ; 17
+";
+
+#[test]
+fn splits_blocks_and_builds_the_call_graph() {
+    let image = assemble(SOURCE).unwrap();
+    let dis = analyze(&image, &[0]);
+    let program = Program::build(&dis);
+
+    assert_eq!(program.blocks.keys().copied().collect::<Vec<_>>(), [0, 3, 10, 14, 15, 17]);
+    assert_eq!(program.blocks[&0].successors, [Edge { target: 3, kind: EdgeKind::Fallthrough }]);
+    assert_eq!(program.blocks[&3].successors, [
+        Edge { target: 14, kind: EdgeKind::False },
+        Edge { target: 10, kind: EdgeKind::True },
+    ]);
+    assert_eq!(program.blocks[&10].calls, [15]);
+    assert_eq!(program.blocks[&10].successors, [Edge { target: 3, kind: EdgeKind::Jump }]);
+
+    let main = &program.functions[&0];
+    assert_eq!(main.blocks.iter().copied().collect::<Vec<_>>(), [0, 3, 10, 14]);
+    assert_eq!(main.callees.iter().copied().collect::<Vec<_>>(), [15]);
+    assert_eq!(program.functions[&15].blocks.iter().copied().collect::<Vec<_>>(), [15, 17]);
+
+    let dot = program.call_graph_dot();
+    assert!(dot.contains("f0 -> f15;"));
+    assert!(program.function_dot(0, &dis).unwrap().contains("b3 -> b14 [label=\"false\""));
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use synacor::asm::assemble;
+use synacor::loader::write_binary;
+
+mod common;
+
+/// Runs the binary with `args` in `dir` and no stdin.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    fs::create_dir_all(dir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_synacor-rust"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn program(dir: &Path, name: &str, source: &str) {
+    fs::create_dir_all(dir).unwrap();
+    write_binary(dir.join(name), &assemble(source).unwrap()).unwrap();
+}
+
+#[test]
+fn exit_codes() {
+    let dir = common::TempDir::new("cli-exit");
+    let dir = dir.0.as_path();
+    program(dir, "ok.bin", "out 'k'\nhalt\n");
+    let output = run(dir, &["run", "ok.bin"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"k");
+
+    // 22 is not an opcode.
+    fs::write(dir.join("fault.bin"), [22, 0]).unwrap();
+    let output = run(dir, &["run", "fault.bin"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output).trim(), "invalid operation 22 at 0");
+
+    let output = run(dir, &["run", "ok.bin", "--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("unknown option --bogus\n\nusage:"));
+
+    let output = run(dir, &["run", "missing.bin"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).starts_with("missing.bin: "));
+
+    program(dir, "in.bin", "in r0\nhalt\n");
+    let output = run(dir, &["run", "in.bin"]);
+    assert_eq!(output.status.code(), Some(4));
+
+    fs::write(dir.join("bad.asm"), "bogus r0\n").unwrap();
+    assert_eq!(run(dir, &["asm", "bad.asm"]).status.code(), Some(5));
+
+    let output = run(dir, &["decompile", "ok.bin", "--function", "1"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(stderr(&output).trim(), "ok.bin: no function starts at 1");
+}
+
+#[test]
+fn trace_subcommand_records_every_instruction() {
+    let dir = common::TempDir::new("cli-trace");
+    let dir = dir.0.as_path();
+    program(dir, "ok.bin", "noop\nout 'k'\nhalt\n");
+    assert_eq!(run(dir, &["trace", "ok.bin"]).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(dir.join("trace.jsonl")).unwrap().lines().count(), 3);
+
+    assert_eq!(run(dir, &["trace", "ok.bin", "--trace", "t.jsonl", "--trace-op", "out"]).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(dir.join("t.jsonl")).unwrap().lines().count(), 1);
+
+    assert_eq!(run(dir, &["trace", "ok.bin", "--trace-format", "binary"]).status.code(), Some(0));
+    assert!(dir.join("trace.bin").exists());
+}
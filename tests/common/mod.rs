@@ -0,0 +1,76 @@
+//! Fixtures shared by the integration tests. Each test binary uses only
+//! some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use synacor::asm::assemble;
+use synacor::io::{BufferInput, BufferOutput};
+use synacor::CPU;
+
+/// The teleporter confirmation routine from the challenge binary, relocated.
+/// Append it to a program that calls `f`.
+pub const TELEPORTER: &str = "\
+f:      jt r0 m
+        add r0 r1 1
+        ret
+m:      jt r1 n
+        add r0 r0 32767
+        set r1 r7
+        call f
+        ret
+n:      push r0
+        add r1 r1 32767
+        call f
+        set r1 r0
+        pop r0
+        add r0 r0 32767
+        call f
+        ret
+";
+
+/// `main` followed by [`TELEPORTER`].
+pub fn with_teleporter(main: &str) -> String {
+    format!("{}{}", main, TELEPORTER)
+}
+
+/// Counts r0 down from 3 by recursing into `down` at address 6.
+pub const COUNTDOWN: &str = "\
+        set r0 3        ; 0
+        call down       ; 3
+        halt            ; 5
+down:   jf r0 done      ; 6
+        add r0 r0 32767 ; 9
+        call down       ; 13
+done:   ret             ; 15
+";
+
+/// A machine with no input, `source` assembled and loaded, and its output.
+pub fn machine(source: &str) -> (CPU, BufferOutput) {
+    machine_with_input(source, "")
+}
+
+/// Like [`machine`], with the lines of `input` to read.
+pub fn machine_with_input(source: &str, input: &str) -> (CPU, BufferOutput) {
+    let output = BufferOutput::new();
+    let mut cpu = CPU::with_io(Box::new(BufferInput::new(input)), Box::new(output.clone()));
+    cpu.load(&assemble(source).unwrap());
+    (cpu, output)
+}
+
+/// A fresh directory under the system temp dir, removed when dropped.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("synacor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
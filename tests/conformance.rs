@@ -0,0 +1,316 @@
+//! Tiny hand-assembled programs checked against the architecture spec.
+//!
+//! Operands 0..=32767 are literals, 32768..=32775 are registers R0..R7.
+
+use synacor::io::{BufferInput, BufferOutput};
+use synacor::{CPU, Fault, StepResult};
+
+const R0: u16 = 32768;
+const R1: u16 = 32769;
+const R2: u16 = 32770;
+const R7: u16 = 32775;
+
+const HALT: u16 = 0;
+const SET: u16 = 1;
+const PUSH: u16 = 2;
+const POP: u16 = 3;
+const EQ: u16 = 4;
+const GT: u16 = 5;
+const JMP: u16 = 6;
+const JT: u16 = 7;
+const JF: u16 = 8;
+const ADD: u16 = 9;
+const MULT: u16 = 10;
+const MOD: u16 = 11;
+const AND: u16 = 12;
+const OR: u16 = 13;
+const NOT: u16 = 14;
+const RMEM: u16 = 15;
+const WMEM: u16 = 16;
+const CALL: u16 = 17;
+const RET: u16 = 18;
+const OUT: u16 = 19;
+const IN: u16 = 20;
+const NOOP: u16 = 21;
+
+struct Run {
+    cpu: CPU,
+    output: String,
+    result: StepResult,
+}
+
+fn run_with_input(program: &[u16], input: &str) -> Run {
+    let output = BufferOutput::new();
+    let mut cpu = CPU::with_io(Box::new(BufferInput::new(input)), Box::new(output.clone()));
+    cpu.load(program);
+    let result = cpu.run();
+    Run { cpu, output: output.contents(), result }
+}
+
+fn run(program: &[u16]) -> Run {
+    run_with_input(program, "")
+}
+
+#[test]
+fn halt_stops_without_moving_the_program_counter() {
+    let run = run(&[HALT, OUT, 'x' as u16]);
+    assert_eq!(run.result, StepResult::Halted);
+    assert_eq!(run.cpu.pc(), 0);
+    assert!(run.cpu.is_halted());
+    assert_eq!(run.output, "");
+}
+
+#[test]
+fn set_addresses_each_register_by_its_own_index() {
+    let mut program = Vec::new();
+    for reg in 0..8 {
+        program.extend([SET, R0 + reg, 100 + reg]);
+    }
+    program.push(HALT);
+    let run = run(&program);
+    assert_eq!(run.cpu.registers(), &[100, 101, 102, 103, 104, 105, 106, 107]);
+}
+
+#[test]
+fn register_operands_read_the_register_not_the_literal() {
+    let run = run(&[SET, R7, 9, SET, R0, R7, ADD, R1, R7, 1, HALT]);
+    assert_eq!(run.cpu.registers()[0], 9);
+    assert_eq!(run.cpu.registers()[1], 10);
+}
+
+#[test]
+fn push_and_pop_are_last_in_first_out() {
+    let run = run(&[PUSH, 1, PUSH, 2, SET, R2, 3, PUSH, R2, POP, R0, POP, R1, HALT]);
+    assert_eq!(&run.cpu.registers()[..3], &[3, 2, 3]);
+    assert_eq!(run.cpu.stack(), &[1]);
+}
+
+#[test]
+fn pop_from_empty_stack_faults() {
+    let run = run(&[NOOP, POP, R0, HALT]);
+    assert_eq!(run.result, StepResult::Fault(Fault::StackUnderflow { addr: 1 }));
+    assert_eq!(run.cpu.pc(), 1);
+}
+
+#[test]
+fn eq_and_gt_write_one_or_zero() {
+    let run = run(&[EQ, R0, 5, 5, EQ, R1, 5, 6, GT, R2, 6, 5, GT, R7, 5, 5, HALT]);
+    let regs = run.cpu.registers();
+    assert_eq!((regs[0], regs[1], regs[2], regs[7]), (1, 0, 1, 0));
+}
+
+#[test]
+fn jmp_jt_and_jf_follow_the_spec() {
+    let program = [
+        JMP, 4,
+        OUT, 'a' as u16,
+        JT, 0, 9,          // 4: not taken
+        OUT, 'b' as u16,   // 7
+        JT, 7, 14,         // 9: taken
+        OUT, 'c' as u16,
+        JF, 1, 19,         // 14: not taken
+        OUT, 'd' as u16,   // 17
+        JF, 0, 24,         // 19: taken
+        OUT, 'e' as u16,
+        HALT,              // 24
+    ];
+    assert_eq!(run(&program).output, "bd");
+}
+
+#[test]
+fn add_wraps_around_modulo_32768() {
+    let run = run(&[ADD, R0, 32758, 15, ADD, R1, 32767, 1, HALT]);
+    assert_eq!(run.cpu.registers()[0], 5);
+    assert_eq!(run.cpu.registers()[1], 0);
+}
+
+#[test]
+fn mult_wraps_around_modulo_32768() {
+    let run = run(&[MULT, R0, 200, 300, MULT, R1, 32767, 32767, HALT]);
+    assert_eq!(run.cpu.registers()[0], (200 * 300) % 32768);
+    assert_eq!(run.cpu.registers()[1], 1);
+}
+
+#[test]
+fn mod_is_the_remainder() {
+    let run = run(&[MOD, R0, 17, 5, MOD, R1, 4, 9, HALT]);
+    assert_eq!(run.cpu.registers()[0], 2);
+    assert_eq!(run.cpu.registers()[1], 4);
+}
+
+#[test]
+fn mod_by_zero_faults() {
+    let run = run(&[MOD, R0, 17, 0, HALT]);
+    assert_eq!(run.result, StepResult::Fault(Fault::DivisionByZero { addr: 0 }));
+}
+
+#[test]
+fn and_or_are_bitwise() {
+    let run = run(&[AND, R0, 0b1100, 0b1010, OR, R1, 0b1100, 0b1010, HALT]);
+    assert_eq!(run.cpu.registers()[0], 0b1000);
+    assert_eq!(run.cpu.registers()[1], 0b1110);
+}
+
+#[test]
+fn not_keeps_only_fifteen_bits() {
+    let run = run(&[NOT, R0, 0, NOT, R1, 0x5555, NOT, R2, 32767, HALT]);
+    assert_eq!(run.cpu.registers()[0], 32767);
+    assert_eq!(run.cpu.registers()[1], 0x2aaa);
+    assert_eq!(run.cpu.registers()[2], 0);
+}
+
+#[test]
+fn rmem_and_wmem_go_through_memory() {
+    let run = run(&[SET, R1, 100, WMEM, R1, 1234, WMEM, 101, R1, RMEM, R0, 100, RMEM, R2, 101, HALT]);
+    assert_eq!(run.cpu.memory()[100], 1234);
+    assert_eq!(run.cpu.memory()[101], 100);
+    assert_eq!(run.cpu.registers()[0], 1234);
+    assert_eq!(run.cpu.registers()[2], 100);
+}
+
+#[test]
+fn wmem_can_overwrite_code() {
+    // Turns the OUT at 7 into an OUT of 'y' before reaching it.
+    let run = run(&[WMEM, 8, 'y' as u16, NOOP, NOOP, NOOP, NOOP, OUT, 'x' as u16, HALT]);
+    assert_eq!(run.output, "y");
+}
+
+#[test]
+fn call_pushes_the_return_address_and_ret_pops_it() {
+    let program = [
+        CALL, 6,           // 0
+        OUT, 'b' as u16,   // 2
+        HALT,              // 4
+        HALT,
+        OUT, 'a' as u16,   // 6
+        SET, R0, 0,
+        POP, R0,           // return address
+        PUSH, R0,
+        RET,
+    ];
+    let run = run(&program);
+    assert_eq!(run.output, "ab");
+    assert_eq!(run.cpu.registers()[0], 2);
+    assert!(run.cpu.stack().is_empty());
+}
+
+#[test]
+fn call_through_a_register() {
+    let run = run(&[SET, R0, 6, CALL, R0, HALT, OUT, 'z' as u16, RET]);
+    assert_eq!(run.output, "z");
+    assert_eq!(run.result, StepResult::Halted);
+}
+
+#[test]
+fn ret_with_empty_stack_halts() {
+    let run = run(&[OUT, 'r' as u16, RET, OUT, 'x' as u16]);
+    assert_eq!(run.output, "r");
+    assert_eq!(run.result, StepResult::Halted);
+    assert!(run.cpu.is_halted());
+}
+
+#[test]
+fn out_prints_literal_and_register_characters() {
+    let run = run(&[SET, R0, 'i' as u16, OUT, 'h' as u16, OUT, R0, OUT, '\n' as u16, HALT]);
+    assert_eq!(run.output, "hi\n");
+    assert_eq!(run.cpu.last_output_line(), "hi");
+}
+
+#[test]
+fn in_reads_one_character_at_a_time_including_the_newline() {
+    let run = run_with_input(&[IN, R0, IN, R1, IN, R2, HALT], "ab\n");
+    assert_eq!(&run.cpu.registers()[..3], &['a' as u16, 'b' as u16, '\n' as u16]);
+    assert_eq!(run.result, StepResult::Halted);
+}
+
+#[test]
+fn in_without_input_waits_without_executing() {
+    let mut cpu = CPU::with_io(Box::new(BufferInput::default()), Box::new(BufferOutput::new()));
+    cpu.load(&[IN, R0, HALT]);
+    assert_eq!(cpu.step(), StepResult::NeedsInput);
+    assert_eq!(cpu.pc(), 0);
+    cpu.push_input("x");
+    assert_eq!(cpu.step(), StepResult::Continued);
+    assert_eq!(cpu.registers()[0], 'x' as u16);
+    assert_eq!(cpu.pc(), 2);
+}
+
+#[test]
+fn noop_only_advances() {
+    let mut cpu = CPU::new();
+    cpu.load(&[NOOP, NOOP, HALT]);
+    assert_eq!(cpu.step(), StepResult::Continued);
+    assert_eq!(cpu.pc(), 1);
+    assert_eq!(cpu.registers(), &[0; 8]);
+}
+
+#[test]
+fn every_opcode_runs_in_one_program() {
+    let program = [
+        SET, R0, 3,             // 0
+        PUSH, R0,               // 3
+        POP, R1,                // 5
+        EQ, R2, R0, R1,         // 7
+        GT, R2, R0, 1,          // 11
+        JMP, 18,                // 15
+        HALT,                   // 17
+        JT, R2, 22,             // 18
+        HALT,                   // 21
+        JF, 0, 26,              // 22
+        HALT,                   // 25
+        ADD, R0, R0, 32767,     // 26: 3 + 32767 wraps to 2
+        MULT, R0, R0, 16384,    // 30: 2 * 16384 wraps to 0
+        MOD, R1, 10, 4,         // 34
+        AND, R1, R1, 3,         // 38
+        OR, R1, R1, 64,         // 42
+        NOT, R2, 32767,         // 46
+        WMEM, 200, R1,          // 49
+        RMEM, R7, 200,          // 52
+        CALL, 60,               // 55
+        NOOP,                   // 57
+        RET,                    // 58: empty stack, halts
+        HALT,                   // 59
+        OUT, R7,                // 60
+        IN, R2,                 // 62
+        OUT, R2,                // 64
+        RET,                    // 66
+    ];
+    let run = run_with_input(&program, "!\n");
+    assert_eq!(run.result, StepResult::Halted);
+    assert_eq!(run.output, "B!");
+    assert_eq!(run.cpu.pc(), 58);
+    assert_eq!(run.cpu.registers()[0], 0);
+    assert_eq!(run.cpu.registers()[1], 66);
+    assert_eq!(run.cpu.registers()[2], '!' as u16);
+    assert_eq!(run.cpu.registers()[7], 66);
+    assert_eq!(run.cpu.memory()[200], 66);
+    assert!(run.cpu.stack().is_empty());
+}
+
+#[test]
+fn unknown_opcode_faults() {
+    let run = run(&[NOOP, 22]);
+    assert_eq!(run.result, StepResult::Fault(Fault::InvalidOpcode { addr: 1, opcode: 22 }));
+    assert_eq!(run.cpu.pc(), 1);
+}
+
+#[test]
+fn operand_above_the_registers_faults() {
+    let run = run(&[SET, R0, 32776, HALT]);
+    assert_eq!(run.result, StepResult::Fault(Fault::InvalidOperand { addr: 0, value: 32776 }));
+    assert_eq!(run.cpu.registers()[0], 0);
+}
+
+#[test]
+fn writing_to_a_literal_faults() {
+    let run = run(&[ADD, 5, 1, 2, HALT]);
+    assert_eq!(run.result, StepResult::Fault(Fault::InvalidOperand { addr: 0, value: 5 }));
+}
+
+#[test]
+fn instruction_cut_off_by_the_end_of_memory_faults() {
+    let mut program = vec![NOOP; 32767];
+    program.push(SET);
+    let run = run(&program);
+    assert_eq!(run.result, StepResult::Fault(Fault::AddressOutOfRange { addr: 32767 }));
+}
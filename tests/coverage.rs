@@ -0,0 +1,63 @@
+use synacor::asm::assemble;
+use synacor::cfg::Program;
+use synacor::coverage::{Coverage, CoverageError};
+
+mod common;
+
+const SOURCE: &str = "\
+        in r0           ; 0
+        eq r1 r0 'a'    ; 2
+        jt r1 left      ; 6
+        set r2 right    ; 9
+        call r2         ; 12
+        halt            ; 14
+left:   call other      ; 15
+        halt            ; 17
+other:  ret             ; 18
+right:  ret             ; 19
+";
+
+fn run(input: &str) -> Coverage {
+    let (mut cpu, _) = common::machine_with_input(SOURCE, input);
+    let mut coverage = Coverage::new();
+    cpu.run_with(&mut [&mut coverage]);
+    coverage
+}
+
+#[test]
+fn records_executed_addresses_and_call_targets() {
+    let coverage = run("b");
+    assert!(coverage.is_executed(12));
+    assert!(coverage.is_executed(19));
+    assert!(!coverage.is_executed(15));
+    assert_eq!(coverage.executed_count(), 7);
+    assert_eq!(coverage.call_targets(), vec![19]);
+}
+
+#[test]
+fn merges_and_round_trips_runs() {
+    let mut coverage = run("a");
+    coverage.merge(&run("b"));
+    assert_eq!(coverage.executed_count(), 10);
+    assert_eq!(Coverage::from_bytes(&coverage.to_bytes()).unwrap().to_bytes(), coverage.to_bytes());
+    assert!(matches!(Coverage::from_bytes(b"SYNTRACE"), Err(CoverageError::BadMagic)));
+
+    let binary = assemble(SOURCE).unwrap();
+    let dis = coverage.analyze(&binary, &[0]);
+    let report = coverage.report(&dis, &Program::build(&dis));
+    assert!(report.starts_with("instructions  10/10 (100.0%)"), "{}", report);
+    assert!(report.contains("fn_19"));
+}
+
+#[test]
+fn marks_uncovered_functions_and_lines() {
+    let coverage = run("b");
+    let binary = assemble(SOURCE).unwrap();
+    let dis = coverage.analyze(&binary, &[0]);
+    let report = coverage.report(&dis, &Program::build(&dis));
+    assert!(report.contains("fn_18      uncovered"), "{}", report);
+    assert!(report.contains("fn_0       partial"), "{}", report);
+    let listing = coverage.annotated_listing(&dis, &binary);
+    assert!(listing.lines().any(|line| line.starts_with("+ ") && line.contains("CALL R2")), "{}", listing);
+    assert!(listing.lines().any(|line| line.starts_with("- ") && line.contains("CALL")), "{}", listing);
+}
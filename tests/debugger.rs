@@ -0,0 +1,54 @@
+use synacor::io::BufferInput;
+use synacor::Debugger;
+use synacor::debugger::{Exit, GameLine};
+
+mod common;
+
+#[test]
+fn waiting_for_input_does_not_count_as_another_hit() {
+    // The first IN waits for a line, the second is served from the queue.
+    let (mut cpu, _) = common::machine_with_input("noop\nin r0\nout r0\nin r0\nout r0\nhalt\n", "ab");
+    let mut debugger = Debugger::new();
+    let first = debugger.breakpoints().add(1, None);
+    let second = debugger.breakpoints().add(5, None);
+    for id in [first, second] {
+        debugger.breakpoints().get_mut(id).unwrap().ignore_count = 3;
+    }
+    assert_eq!(debugger.run(&mut cpu), Exit::Halted);
+
+    let counts: Vec<(u64, u64)> = debugger.breakpoints().iter().map(|bp| (bp.hits, bp.ignore_count)).collect();
+    assert_eq!(counts, vec![(1, 2), (1, 2)]);
+}
+
+#[test]
+fn classifies_game_prompt_lines() {
+    assert_eq!(GameLine::classify("!!s\n"), GameLine::Input("!s\n"));
+    assert_eq!(GameLine::classify("!\n"), GameLine::Console);
+    assert_eq!(GameLine::classify("!set 7 1\n"), GameLine::Command("set 7 1"));
+    assert_eq!(GameLine::classify("go north\n"), GameLine::Input("go north\n"));
+}
+
+#[test]
+fn escaped_lines_reach_the_game_and_commands_do_not() {
+    let (mut cpu, _) = common::machine_with_input("in r0\nin r1\nin r2\nhalt\n", "!set 7 1\n!!s");
+    assert_eq!(Debugger::new().run(&mut cpu), Exit::Halted);
+    assert_eq!(&cpu.registers()[..3], &['!' as u16, 's' as u16, '\n' as u16]);
+    assert_eq!(cpu.registers()[7], 1);
+}
+
+#[test]
+fn reverse_commands_have_long_names() {
+    let run = |commands: &str| {
+        let (mut cpu, _) = common::machine("noop\nnoop\nnoop\nhalt\n");
+        cpu.enable_history(1 << 20);
+        let mut debugger = Debugger::new();
+        debugger.set_console_input(Box::new(BufferInput::new(commands)));
+        debugger.stop();
+        assert_eq!(debugger.run(&mut cpu), Exit::Quit);
+        cpu.pc()
+    };
+    assert_eq!(run("s\ns\nreverse-step\nq"), 1);
+    assert_eq!(run("s\ns\nrs\nq"), 1);
+    assert_eq!(run("s\ns\nreverse-continue\nq"), 0);
+    assert_eq!(run("s\ns\nrc\nq"), 0);
+}
@@ -0,0 +1,72 @@
+use synacor::asm::assemble;
+use synacor::cfg::Program;
+use synacor::decompile::decompile_function;
+use synacor::disasm::analyze;
+
+const SOURCE: &str = "\
+        call stars
+        call check
+        halt
+stars:  push r1
+        set r1 r0
+loop:   jf r1 done
+        out '*'
+        add r1 r1 32767
+        jmp loop
+done:   pop r1
+        ret
+check:  push r0
+        call stars
+        pop r1
+        eq r2 r1 3
+        jf r2 bad
+        out 'o'
+        out 'k'
+        jmp end
+bad:    out 'n'
+end:    ret
+";
+
+/// The pseudo-code with address comments and indentation stripped.
+fn code(entry: u16) -> Vec<String> {
+    let image = assemble(SOURCE).unwrap();
+    let dis = analyze(&image, &[0]);
+    let program = Program::build(&dis);
+    decompile_function(&program, &dis, entry).unwrap()
+        .lines()
+        .filter(|line| !line.starts_with("//"))
+        .map(|line| line.split(" //").next().unwrap().trim().to_string())
+        .collect()
+}
+
+#[test]
+fn loops_become_while_and_saved_registers_disappear() {
+    assert_eq!(code(5), [
+        "void fn_5() {",
+        "r1 = r0;",
+        "while (r1) {",
+        "print(\"*\");",
+        "r1 = r1 + 32767;",
+        "}",
+        "return;",
+        "}",
+    ]);
+}
+
+#[test]
+fn branches_become_if_else_and_stack_slots_become_locals() {
+    assert_eq!(code(24), [
+        "void fn_24() {",
+        "local0 = r0;",
+        "fn_5();",
+        "r1 = local0;",
+        "r2 = r1 == 3;",
+        "if (r2) {",
+        "print(\"ok\");",
+        "} else {",
+        "print(\"n\");",
+        "}",
+        "return;",
+        "}",
+    ]);
+}
@@ -0,0 +1,43 @@
+use synacor::diff::{changed_n_times, MemoryChange, SnapshotDiff, StackDiff};
+use synacor::SaveState;
+
+fn state(memory: &[u16], registers: [u16; 8], stack: &[u16]) -> SaveState {
+    SaveState {
+        memory: memory.to_vec(),
+        registers,
+        stack: stack.to_vec(),
+        input_queue: Vec::new(),
+        pc: 0,
+        halted: false,
+        saved_at: 0,
+        last_output: String::new(),
+    }
+}
+
+#[test]
+fn groups_changed_words_into_ranges() {
+    let before = state(&[1, 2, 3, 4, 5, 6], [0, 7, 0, 0, 0, 0, 0, 0], &[10, 20, 30]);
+    let after = state(&[1, 9, 9, 4, 5, 0], [0, 8, 0, 0, 0, 0, 0, 0], &[10, 25]);
+    let diff = SnapshotDiff::new(&before, &after);
+    assert_eq!(diff.registers, vec![(1, 7, 8)]);
+    assert_eq!(diff.memory, vec![
+        MemoryChange { start: 1, before: vec![2, 3], after: vec![9, 9] },
+        MemoryChange { start: 5, before: vec![6], after: vec![0] },
+    ]);
+    assert_eq!(diff.stack, StackDiff { common: 1, removed: vec![20, 30], added: vec![25] });
+    assert!(diff.to_string().contains("1..2 (2 words)"));
+    assert!(SnapshotDiff::new(&before, &before).is_empty());
+}
+
+#[test]
+fn filters_words_by_number_of_changes() {
+    let regs = [0; 8];
+    let states = [
+        state(&[0, 5, 1], regs, &[]),
+        state(&[1, 5, 2], regs, &[]),
+        state(&[1, 6, 3], regs, &[]),
+    ];
+    assert_eq!(changed_n_times(&states, 1), vec![(0, vec![0, 1, 1]), (1, vec![5, 5, 6])]);
+    assert_eq!(changed_n_times(&states, 2), vec![(2, vec![1, 2, 3])]);
+    assert!(changed_n_times(&states, 0).is_empty());
+}
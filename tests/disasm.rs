@@ -0,0 +1,73 @@
+use synacor::asm::assemble;
+use synacor::disasm::analyze;
+
+const SOURCE: &str = "\
+        out 'o'
+        out 'k'
+        call greet
+        set r0 3
+loop:   add r0 r0 32767
+        jt r0 loop
+        halt
+        .data 9, 1
+greet:  ret
+";
+
+#[test]
+fn follows_control_flow_and_labels_targets() {
+    let image = assemble(SOURCE).unwrap();
+    let disassembly = analyze(&image, &[0]);
+    assert!(disassembly.functions.contains(&19));
+    assert!(disassembly.jump_targets.contains(&9));
+    assert!(!disassembly.is_code(17));
+    assert!(!disassembly.is_code(18));
+
+    let listing = disassembly.listing(&image);
+    let lines: Vec<&str> = listing.lines().map(|line| line.split(" ;").next().unwrap().trim_end()).collect();
+    assert_eq!(lines, [
+        "fn_0:",
+        "    0  OUT \"ok\"",
+        "    4  CALL fn_19",
+        "    6  SET R0 3",
+        "L9:",
+        "    9  ADD R0 R0 32767",
+        "   13  JT R0 L9",
+        "   16  HALT",
+        "   17  .data 9, 1",
+        "",
+        "fn_19:",
+        "   19  RET",
+    ]);
+}
+
+#[test]
+fn follows_register_targets_set_from_literals() {
+    let image = assemble("\
+        set r0 f
+        set r1 r0
+        call r1         ; 6
+        set r2 g
+        add r2 r2 1
+        call r2         ; 15
+        wmem 23 6       ; 17
+        wmem 24 h       ; 20
+        halt            ; 23
+        .data 0
+f:      ret
+g:      ret
+h:      set r3 k
+        jmp r3
+        .data 7
+k:      halt
+").unwrap();
+    let disassembly = analyze(&image, &[0]);
+    let f = image[2];
+    assert!(disassembly.functions.contains(&f));
+    assert!(disassembly.is_code(f));
+    assert!(!disassembly.is_code(f + 1));
+    let h = image[22];
+    assert_eq!(disassembly.format_instruction(&disassembly.instructions[&23]), format!("JMP L{}", h));
+    assert!(disassembly.is_code(h));
+    assert!(!disassembly.is_code(h + 5));
+    assert!(disassembly.is_code(h + 6));
+}
@@ -0,0 +1,22 @@
+use synacor::flame::FoldedStacks;
+
+mod common;
+
+#[test]
+fn folded_stacks_count_instructions_per_call_path() {
+    let run = |merge: bool| {
+        let (mut cpu, _) = common::machine(common::COUNTDOWN);
+        let mut flame = FoldedStacks::new();
+        flame.set_merge_recursion(merge);
+        cpu.run_with(&mut [&mut flame]);
+        flame.folded()
+    };
+    assert_eq!(run(false), "\
+fn_0 3
+fn_0;fn_6 4
+fn_0;fn_6;fn_6 4
+fn_0;fn_6;fn_6;fn_6 4
+fn_0;fn_6;fn_6;fn_6;fn_6 2
+");
+    assert_eq!(run(true), "fn_0 3\nfn_0;fn_6 14\n");
+}
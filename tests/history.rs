@@ -0,0 +1,86 @@
+use std::mem::size_of;
+use synacor::history::{History, StackChange, UndoRecord};
+use synacor::{CPU, Frame, SaveState, StepResult};
+
+mod common;
+
+const SOURCE: &str = "\
+        set r0 5
+        push r0
+        call f          ; 5
+        pop r1
+        in r2
+        in r3
+        halt
+f:      wmem 200 r0     ; 14
+        add r0 r0 1
+        ret
+";
+
+fn cpu() -> CPU {
+    let (mut cpu, _) = common::machine(SOURCE);
+    cpu.push_input("ab");
+    cpu.enable_history(1024 * 1024);
+    cpu
+}
+
+fn state(cpu: &CPU) -> (SaveState, Vec<Frame>) {
+    let mut state = cpu.snapshot();
+    state.saved_at = 0;
+    (state, cpu.call_stack().to_vec())
+}
+
+#[test]
+fn stepping_back_restores_every_earlier_state() {
+    let mut cpu = cpu();
+    let mut states = vec![state(&cpu)];
+    for _ in 0..9 {
+        assert_eq!(cpu.step(), StepResult::Continued);
+        states.push(state(&cpu));
+    }
+    assert_eq!(cpu.memory()[200], 5);
+    assert_eq!(&cpu.registers()[..4], &[6, 5, 'a' as u16, 'b' as u16]);
+
+    states.pop();
+    while let Some(expected) = states.pop() {
+        assert!(cpu.step_back());
+        assert_eq!(state(&cpu), expected);
+    }
+    assert!(!cpu.step_back());
+
+    // The input that was consumed is back on the queue.
+    for _ in 0..9 {
+        cpu.step();
+    }
+    assert_eq!(cpu.registers()[2], 'a' as u16);
+}
+
+#[test]
+fn hooked_call_clears_history() {
+    let mut cpu = cpu();
+    cpu.hooks().insert(14, "noop", Box::new(|_| true));
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.history().unwrap().len(), 2);
+    cpu.step();
+    assert_eq!(cpu.last_hook(), Some(14));
+    assert!(cpu.history().unwrap().is_empty());
+    assert!(!cpu.step_back());
+}
+
+#[test]
+fn budget_evicts_the_oldest_records() {
+    let record = |pc| UndoRecord { pc, register: None, memory: None, stack: StackChange::None, input: None, frame: None };
+    let mut history = History::with_budget(3 * size_of::<UndoRecord>());
+    assert_eq!(history.capacity(), 3);
+    for pc in 0..5 {
+        history.push(record(pc));
+    }
+    assert_eq!(history.len(), 3);
+    let pcs: Vec<u16> = std::iter::from_fn(|| history.pop()).map(|record| record.pc).collect();
+    assert_eq!(pcs, vec![4, 3, 2]);
+
+    let mut empty = History::with_budget(0);
+    empty.push(record(0));
+    assert!(empty.is_empty());
+}
@@ -0,0 +1,78 @@
+use synacor::asm::assemble;
+use synacor::hook::{builtin, teleporter};
+use synacor::trace::{read_binary, Format, Tracer, Filter};
+use synacor::CPU;
+
+mod common;
+
+const MAIN: &str = "\
+        set r0 3
+        set r1 1
+        set r7 2
+        call f          ; 9
+        halt
+";
+
+fn cpu() -> CPU {
+    common::machine(&common::with_teleporter(MAIN)).0
+}
+
+fn f() -> u16 {
+    assemble(&common::with_teleporter(MAIN)).unwrap()[10]
+}
+
+#[test]
+fn teleporter_hook_matches_the_interpreted_routine() {
+    let mut interpreted = cpu();
+    interpreted.run();
+    let f = f();
+
+    let mut hooked = cpu();
+    hooked.hooks().insert(f, "teleporter", builtin("teleporter").unwrap());
+    let mut tracer = Tracer::new(Vec::new(), Format::Binary, Filter::default());
+    hooked.run_with(&mut [&mut tracer]);
+    assert_eq!(hooked.registers(), interpreted.registers());
+    assert_eq!(hooked.stack(), interpreted.stack());
+    assert_eq!(interpreted.registers()[0], teleporter(3, 1, 2));
+
+    let records = read_binary(&tracer.finish().unwrap()).unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[3].pc, 9);
+    assert_eq!(records[3].hook, Some(f));
+    assert!(records[3].to_json().ends_with(&format!(",\"hook\":{}}}", f)));
+}
+
+#[test]
+fn disabled_hooks_fall_back_to_the_guest_code() {
+    let mut cpu = cpu();
+    let f = f();
+    cpu.hooks().insert(f, "zero", Box::new(|ctx| {
+        ctx.registers[0] = 0;
+        true
+    }));
+    cpu.hooks().set_enabled(false);
+    cpu.run();
+    assert_eq!(cpu.registers()[0], teleporter(3, 1, 2));
+    assert_eq!(teleporter(0, 32767, 0), 0);
+}
+
+#[test]
+fn teleporter_hook_declines_registers_above_15_bits() {
+    let run = |hooked: bool| {
+        let mut cpu = cpu();
+        if hooked {
+            let f = f();
+            cpu.hooks().insert(f, "teleporter", builtin("teleporter").unwrap());
+        }
+        for _ in 0..3 {
+            cpu.step();
+        }
+        cpu.set_register(0, 1);
+        cpu.set_register(1, 32768);
+        cpu.set_register(7, 40000);
+        cpu.run();
+        *cpu.registers()
+    };
+    assert_eq!(run(true), run(false));
+    assert_eq!(teleporter(1, 32769, 40000), teleporter(1, 1, 7232));
+}
@@ -0,0 +1,33 @@
+use synacor::asm::assemble;
+use synacor::io::{BufferInput, BufferOutput, InputSource, ScriptInput};
+use synacor::{CPU, StepResult};
+
+#[test]
+fn scripts_replay_in_order_then_fall_back() {
+    let mut input = ScriptInput::new();
+    input.push_script("north\nsouth");
+    input.push_script("take lamp\n");
+    input.set_fallback(Box::new(BufferInput::new("look")));
+    let lines: Vec<String> = std::iter::from_fn(|| input.read_line()).collect();
+    assert_eq!(lines, vec!["north\n", "south\n", "take lamp\n", "look\n"]);
+
+    let mut without_fallback = ScriptInput::new();
+    without_fallback.push_script("north");
+    assert_eq!(without_fallback.read_line().as_deref(), Some("north\n"));
+    assert_eq!(without_fallback.read_line(), None);
+}
+
+#[test]
+fn echoes_only_replayed_lines() {
+    // Echo every character read until the input runs out.
+    let program = assemble("loop: in r0\nout '>'\njmp loop\n").unwrap();
+    let mut input = ScriptInput::new();
+    input.push_script("ab");
+    input.set_fallback(Box::new(BufferInput::new("c")));
+    input.set_echo(true);
+    let output = BufferOutput::new();
+    let mut cpu = CPU::with_io(Box::new(input), Box::new(output.clone()));
+    cpu.load(&program);
+    assert_eq!(cpu.run(), StepResult::NeedsInput);
+    assert_eq!(output.contents(), "ab\n>>>>>");
+}
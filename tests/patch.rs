@@ -0,0 +1,52 @@
+use synacor::patch::{parse, Patch};
+
+mod common;
+
+#[test]
+fn parses_words_assembly_and_blocks() {
+    let patches = parse("\
+# comment
+5489: 21 21
+5489 expect call 6027: noop
+100:
+    jmp here
+    here: halt
+").unwrap();
+    assert_eq!(patches, vec![
+        Patch { line: 2, addr: 5489, words: vec![21, 21], expect: None },
+        Patch { line: 3, addr: 5489, words: vec![21], expect: Some(vec![17, 6027]) },
+        Patch { line: 4, addr: 100, words: vec![6, 102, 0], expect: None },
+    ]);
+    assert_eq!(parse("7: out '#'  # a hash\n").unwrap(), vec![Patch { line: 1, addr: 7, words: vec![19, '#' as u16], expect: None }]);
+    assert_eq!(parse("7:\n    out '#'  # a hash\n    out 'x'\n").unwrap()[0].words, vec![19, 35, 19, 120]);
+    assert_eq!(parse("1:\n    bogus\n").unwrap_err().line, 2);
+    assert_eq!(parse("\n  noop\n").unwrap_err().line, 2);
+    assert_eq!(parse("32767: 1 2\n").unwrap_err().line, 1);
+}
+
+#[test]
+fn applies_checks_and_reverts() {
+    let (mut cpu, output) = common::machine("out 'a'\nhalt\n");
+    assert!(cpu.apply_patch(parse("0 expect 19 98: 19 98").unwrap().remove(0)).is_err());
+    let id = cpu.apply_patch(parse("0 expect out 'a': out 'b'").unwrap().remove(0)).unwrap();
+    assert_eq!(cpu.memory()[1], 'b' as u16);
+    assert_eq!(cpu.patches().iter().count(), 1);
+
+    cpu.revert_patch(id).unwrap();
+    assert_eq!(cpu.memory()[1], 'a' as u16);
+    assert!(cpu.revert_patch(id).is_err());
+    cpu.reapply_patch(id).unwrap();
+    cpu.run();
+    assert_eq!(output.contents(), "b");
+}
+
+#[test]
+fn restoring_a_snapshot_forgets_patches() {
+    let (mut cpu, _) = common::machine("out 'a'\nhalt\n");
+    let before = cpu.snapshot();
+    cpu.apply_patch(parse("1: 98").unwrap().remove(0)).unwrap();
+    cpu.restore(&before);
+    assert!(cpu.patches().is_empty());
+    assert!(cpu.revert_patch(1).is_err());
+    assert_eq!(cpu.memory()[1], 'a' as u16);
+}
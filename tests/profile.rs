@@ -0,0 +1,19 @@
+use synacor::profile::{FunctionStats, Profiler};
+
+mod common;
+
+#[test]
+fn counts_addresses_and_recursive_functions_once() {
+    let (mut cpu, _) = common::machine(common::COUNTDOWN);
+    let mut profiler = Profiler::new();
+    cpu.run_with(&mut [&mut profiler]);
+
+    // 3 in main, then 4 activations of down: 3 x (JF, ADD, CALL, RET) + (JF, RET).
+    assert_eq!(profiler.total(), 17);
+    assert_eq!(profiler.count(6), 4);
+    assert_eq!(profiler.count(15), 4);
+    let functions = profiler.functions();
+    assert_eq!(functions[&0], FunctionStats { calls: 1, exclusive: 3, inclusive: 17 });
+    assert_eq!(functions[&6], FunctionStats { calls: 4, exclusive: 14, inclusive: 14 });
+    assert!(profiler.report(&cpu, 3).contains("fn_6"));
+}
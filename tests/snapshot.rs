@@ -0,0 +1,83 @@
+use std::fs;
+use synacor::snapshot::{SaveSlots, SnapshotError, MAGIC};
+use synacor::SaveState;
+
+mod common;
+
+fn state(pc: u16, saved_at: u64) -> SaveState {
+    let mut memory = vec![0; 32768];
+    memory[..4].copy_from_slice(&[9, 32768, 32769, 4]);
+    SaveState {
+        memory,
+        registers: [1, 2, 3, 4, 5, 6, 7, 25734],
+        stack: vec![10, 20],
+        input_queue: vec!['n' as u16, '\n' as u16],
+        pc,
+        halted: false,
+        saved_at,
+        last_output: String::from("What do you do?"),
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash: u32, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+#[test]
+fn round_trips() {
+    let state = state(1234, 1_700_000_000);
+    assert_eq!(SaveState::from_bytes(&state.to_bytes()).unwrap(), state);
+}
+
+#[test]
+fn rejects_damaged_files() {
+    let bytes = state(1234, 0).to_bytes();
+
+    let mut flipped = bytes.clone();
+    flipped[100] ^= 1;
+    assert!(matches!(SaveState::from_bytes(&flipped), Err(SnapshotError::ChecksumMismatch { .. })));
+
+    assert!(matches!(SaveState::from_bytes(&bytes[..12]), Err(SnapshotError::Truncated)));
+    // Cut inside the memory words, with a checksum that matches what is left.
+    let mut cut = bytes[..1000].to_vec();
+    let checksum = fnv1a(&cut);
+    cut.extend_from_slice(&checksum.to_le_bytes());
+    assert!(matches!(SaveState::from_bytes(&cut), Err(SnapshotError::Truncated)));
+
+    let mut long = state(1234, 0);
+    long.memory.push(0);
+    assert!(matches!(SaveState::from_bytes(&long.to_bytes()), Err(SnapshotError::BadMemorySize(32769))));
+
+    let mut magic = bytes.clone();
+    magic[0] = b'X';
+    assert!(matches!(SaveState::from_bytes(&magic), Err(SnapshotError::BadMagic)));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut bytes = state(1234, 0).to_bytes();
+    bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+    assert!(matches!(SaveState::from_bytes(&bytes), Err(SnapshotError::UnsupportedVersion(2))));
+}
+
+#[test]
+fn slots_validate_names_and_list_newest_first() {
+    let dir = common::TempDir::new("slots");
+    let slots = SaveSlots::new(&dir.0);
+    assert!(slots.list().unwrap().is_empty());
+    assert!(matches!(slots.save("../x", &state(0, 0)), Err(SnapshotError::BadSlotName(_))));
+    assert!(matches!(slots.load("a/b"), Err(SnapshotError::BadSlotName(_))));
+
+    slots.save("old", &state(1, 100)).unwrap();
+    slots.save("new", &state(2, 300)).unwrap();
+    slots.save("middle", &state(3, 200)).unwrap();
+    fs::write(dir.0.join("junk.sav"), b"not a save").unwrap();
+    let names: Vec<String> = slots.list().unwrap().into_iter().map(|slot| slot.name).collect();
+    assert_eq!(names, vec!["new", "middle", "old"]);
+    assert_eq!(slots.load("middle").unwrap().pc, 3);
+
+    slots.delete("old").unwrap();
+    assert!(matches!(slots.delete("old"), Err(SnapshotError::Io(_))));
+    assert!(matches!(slots.load("old"), Err(SnapshotError::Io(_))));
+    assert_eq!(slots.list().unwrap().len(), 2);
+}
@@ -0,0 +1,39 @@
+use synacor::asm::assemble;
+use synacor::solve::{solve_teleporter, TeleporterCheck};
+
+mod common;
+
+/// The teleporter check from the challenge binary, relocated.
+const MAIN: &str = "\
+        out 'a'
+        set r0 4        ; 2
+        set r1 1
+        call f          ; 8
+        eq r1 r0 6
+        jf r1 bad
+        out 'y'
+        halt
+bad:    out 'n'
+        halt
+";
+
+#[test]
+fn finds_the_check_and_the_r7_that_passes_it() {
+    let program = assemble(&common::with_teleporter(MAIN)).unwrap();
+    let check = TeleporterCheck::find(&program).unwrap();
+    assert_eq!((check.call_site, check.function), (8, program[9]));
+    assert_eq!((check.m, check.n, check.result, check.expected), (4, 1, 1, 6));
+    assert_eq!(solve_teleporter(&check, 25728..=25740, 3), vec![25734]);
+    assert!(TeleporterCheck::find(&program[..8]).is_none());
+}
+
+#[test]
+fn patch_skips_the_check() {
+    let program = assemble(&common::with_teleporter(MAIN)).unwrap();
+    let check = TeleporterCheck::find(&program).unwrap();
+    let (mut cpu, output) = common::machine(&common::with_teleporter(MAIN));
+    cpu.apply_patch(check.patch(cpu.memory())).unwrap();
+    cpu.run();
+    assert_eq!(output.contents(), "ay");
+    assert_eq!(check.patch(&program).to_string(), format!("8 expect 17 {} 4 32769 32768 6: 1 32768 6 1 32769 1", program[9]));
+}
@@ -0,0 +1,46 @@
+use synacor::trace::{read_binary, Filter, Format, Tracer};
+use synacor::{Opcode, StepResult};
+
+mod common;
+
+const SOURCE: &str = "\
+        set r0 7
+        push r0
+        wmem 100 r0
+        call f
+        halt
+f:      out 'x'
+        ret
+";
+
+fn trace(format: Format, filter: Filter) -> Vec<u8> {
+    let (mut cpu, _) = common::machine(SOURCE);
+    let mut tracer = Tracer::new(Vec::new(), format, filter);
+    assert_eq!(cpu.run_with(&mut [&mut tracer]), StepResult::Halted);
+    assert_eq!(tracer.steps(), 7);
+    tracer.finish().unwrap()
+}
+
+#[test]
+fn jsonl_records_every_change() {
+    let text = String::from_utf8(trace(Format::Jsonl, Filter::default())).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines, [
+        r#"{"step":0,"pc":0,"op":"SET","args":["R0","7"],"values":[0,7],"regs":[[0,0,7]]}"#,
+        r#"{"step":1,"pc":3,"op":"PUSH","args":["R0"],"values":[7],"stack":{"depth":1,"top":7}}"#,
+        r#"{"step":2,"pc":5,"op":"WMEM","args":["100","R0"],"values":[100,7],"mem":[100,0,7]}"#,
+        r#"{"step":3,"pc":8,"op":"CALL","args":["11"],"values":[11],"stack":{"depth":2,"top":10}}"#,
+        r#"{"step":4,"pc":11,"op":"OUT","args":["120"],"values":[120],"out":"x"}"#,
+        r#"{"step":5,"pc":13,"op":"RET","args":[],"values":[],"stack":{"depth":1,"top":7}}"#,
+        r#"{"step":6,"pc":10,"op":"HALT","args":[],"values":[]}"#,
+    ]);
+}
+
+#[test]
+fn binary_round_trips_and_filters_apply() {
+    let filter = Filter { opcodes: vec![Opcode::Call, Opcode::Ret, Opcode::Wmem], steps: Some(0..=4), ..Filter::default() };
+    let records = read_binary(&trace(Format::Binary, filter.clone())).unwrap();
+    let json = String::from_utf8(trace(Format::Jsonl, filter)).unwrap();
+    assert_eq!(records.iter().map(|record| record.step).collect::<Vec<_>>(), [2, 3]);
+    assert_eq!(records.iter().map(|record| record.to_json() + "\n").collect::<String>(), json);
+}
@@ -0,0 +1,79 @@
+use synacor::io::BufferInput;
+use synacor::cpu::Frame;
+use synacor::debugger::Exit;
+use synacor::watch::Access;
+use synacor::{CPU, Debugger};
+
+mod common;
+
+#[test]
+fn execute_watch_fires_once_per_completed_in() {
+    let (mut cpu, _) = common::machine_with_input("start: in r0\nout r0\neq r1 r0 'b'\njf r1 start\nhalt\n", "a\nb");
+    let id = cpu.watchpoints().add(0, 0, false, false, true, false);
+    cpu.run();
+
+    // Two lines of two characters each; the machine waited before each line.
+    let hits = cpu.take_watch_hits();
+    assert_eq!(hits.len(), 3);
+    assert!(hits.iter().all(|hit| hit.access == Access::Execute && hit.addr == 0));
+    assert_eq!(cpu.watchpoints().get_mut(id).unwrap().hits, 3);
+}
+
+/// `f` reads 100 and writes 101 and 102.
+const ACCESSES: &str = "\
+        call f          ; 0
+        halt
+f:      rmem r0 100     ; 3
+        wmem 101 7      ; 6
+        wmem 102 r0     ; 9
+        ret
+";
+
+fn accesses() -> CPU {
+    let (mut cpu, _) = common::machine(ACCESSES);
+    cpu.memory_mut()[100..103].copy_from_slice(&[42, 5, 6]);
+    cpu
+}
+
+/// `(access, addr, old, new, pc)` of every hit of a watchpoint on `start..=end`.
+fn hits(start: u16, end: u16, read: bool, write: bool) -> Vec<(Access, u16, u16, u16, u16)> {
+    let mut cpu = accesses();
+    cpu.watchpoints().add(start, end, read, write, false, false);
+    cpu.run();
+    let frame = Frame { call_site: 0, function: 3 };
+    cpu.take_watch_hits().into_iter()
+        .inspect(|hit| assert_eq!(hit.call_stack, vec![frame]))
+        .map(|hit| (hit.access, hit.addr, hit.old, hit.new, hit.pc))
+        .collect()
+}
+
+#[test]
+fn rmem_hits_read_watchpoints() {
+    assert_eq!(hits(100, 100, true, false), vec![(Access::Read, 100, 42, 42, 3)]);
+    assert_eq!(hits(99, 102, true, false), vec![(Access::Read, 100, 42, 42, 3)]);
+    assert_eq!(hits(101, 102, true, false), vec![]);
+}
+
+#[test]
+fn wmem_hits_write_watchpoints() {
+    assert_eq!(hits(101, 101, false, true), vec![(Access::Write, 101, 5, 7, 6)]);
+    assert_eq!(hits(100, 102, false, true), vec![(Access::Write, 101, 5, 7, 6), (Access::Write, 102, 6, 42, 9)]);
+}
+
+/// Where the debugger stops for a breaking watchpoint, quitting at the console.
+fn stop_at(start: u16, end: u16, kinds: (bool, bool, bool)) -> (Exit, usize, u16) {
+    let mut cpu = accesses();
+    cpu.watchpoints().add(start, end, kinds.0, kinds.1, kinds.2, true);
+    let mut debugger = Debugger::new();
+    debugger.set_console_input(Box::new(BufferInput::new("q")));
+    let exit = debugger.run(&mut cpu);
+    (exit, cpu.pc(), cpu.memory()[101])
+}
+
+#[test]
+fn breaking_watchpoints_stop_the_debugger() {
+    // Reads and writes stop after the instruction, executes before it.
+    assert_eq!(stop_at(100, 100, (true, false, false)), (Exit::Quit, 6, 5));
+    assert_eq!(stop_at(101, 102, (false, true, false)), (Exit::Quit, 9, 7));
+    assert_eq!(stop_at(6, 6, (false, false, true)), (Exit::Quit, 6, 5));
+}